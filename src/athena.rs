@@ -0,0 +1,193 @@
+//! # athena
+//! Previews the first rows of a source table with an Athena query, so the user can check they
+//! picked the right table before an instance is launched. Queries write their results next to
+//! the table, in the same `_athena` prefix the worker uses, and the CSV is read back from S3.
+use crate::aws_context::AwsContext;
+use crate::error::{Error, Result, ResultExt};
+use crate::get_glue_data::{split_s3_location, GlueTable};
+use crate::retry::wait_until;
+use aws_sdk_athena::model::{QueryExecution, QueryExecutionState, ResultConfiguration};
+use std::time::Duration;
+
+/// Time a preview query gets to complete
+const QUERY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Returns the header and the first `rows` rows of the table
+pub async fn preview_rows(
+    ctx: &AwsContext,
+    table: &GlueTable,
+    rows: u64,
+) -> Result<Vec<Vec<String>>> {
+    let region = table.database().region();
+    let athena = ctx.athena(region);
+    let action = format!("preview table {} with Athena", table.name());
+    let query = format!(
+        "SELECT * FROM \"{}\".\"{}\" LIMIT {}",
+        table.database().query_name(),
+        table.name(),
+        rows
+    );
+    let output_location = format!(
+        "{}_athena/preview/",
+        table.s3_location().trim_end_matches('/')
+    );
+    let query_execution_id = athena
+        .start_query_execution()
+        .query_string(query)
+        .result_configuration(
+            ResultConfiguration::builder()
+                .output_location(output_location)
+                .build(),
+        )
+        .send()
+        .await
+        .context(&action)?
+        .query_execution_id()
+        .map(|query_execution_id| query_execution_id.to_string())
+        .ok_or_else(|| Error::unexpected_response(&action, "query execution id"))?;
+
+    let get_query_execution = || async {
+        athena
+            .get_query_execution()
+            .query_execution_id(&query_execution_id)
+            .send()
+            .await
+            .context(&action)?
+            .query_execution()
+            .cloned()
+            .ok_or_else(|| Error::unexpected_response(&action, "query execution"))
+    };
+    let state = |query_execution: &QueryExecution| {
+        query_execution
+            .status()
+            .and_then(|status| status.state())
+            .cloned()
+    };
+
+    wait_until("the preview query to complete", QUERY_TIMEOUT, || async {
+        let query_execution = get_query_execution().await?;
+        match state(&query_execution) {
+            Some(QueryExecutionState::Succeeded) => Ok(true),
+            Some(QueryExecutionState::Failed) | Some(QueryExecutionState::Cancelled) => {
+                Err(Error::Aws {
+                    action: action.clone(),
+                    code: "QueryFailed".to_string(),
+                    message: query_execution
+                        .status()
+                        .and_then(|status| status.state_change_reason())
+                        .unwrap_or_default()
+                        .to_string(),
+                })
+            }
+            _ => Ok(false),
+        }
+    })
+    .await?;
+
+    // the results are a CSV file, read it and remove it along with its metadata
+    let result_location = get_query_execution()
+        .await?
+        .result_configuration()
+        .and_then(|result_configuration| result_configuration.output_location())
+        .map(|output_location| output_location.to_string())
+        .ok_or_else(|| Error::unexpected_response(&action, "result location"))?;
+    let (bucket, key) = split_s3_location(&result_location)
+        .ok_or_else(|| Error::unexpected_response(&action, "S3 result location"))?;
+    let s3 = ctx.s3(region);
+    let body = s3
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .context(&action)?
+        .body
+        .collect()
+        .await
+        .map_err(|err| Error::Network {
+            action: action.clone(),
+            message: err.to_string(),
+        })?
+        .into_bytes();
+    for key in [key.to_string(), format!("{}.metadata", key)] {
+        // leftover results only cost storage, the preview does not fail on them
+        let _ = s3.delete_object().bucket(bucket).key(key).send().await;
+    }
+    Ok(parse_csv(&String::from_utf8_lossy(&body)))
+}
+
+/// Parses the CSV written by Athena, where every field is quoted and quotes are doubled
+fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => in_quotes = !in_quotes,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            ('\r', false) => {}
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_csv_splits_rows_and_fields() {
+        assert_eq!(
+            parse_csv("\"id\",\"name\"\n\"1\",\"alice\"\n"),
+            vec![row(&["id", "name"]), row(&["1", "alice"])]
+        );
+    }
+
+    #[test]
+    fn parse_csv_keeps_commas_and_newlines_of_quoted_fields() {
+        assert_eq!(
+            parse_csv("\"a,b\",\"line\nbreak\"\n\"c\",\"d\"\n"),
+            vec![row(&["a,b", "line\nbreak"]), row(&["c", "d"])]
+        );
+    }
+
+    #[test]
+    fn parse_csv_unescapes_doubled_quotes() {
+        assert_eq!(
+            parse_csv("\"say \"\"hi\"\"\",\"\"\"\"\n"),
+            vec![row(&["say \"hi\"", "\""])]
+        );
+    }
+
+    #[test]
+    fn parse_csv_handles_crlf_and_a_missing_trailing_newline() {
+        assert_eq!(
+            parse_csv("\"id\",\"name\"\r\n\"1\",\r\n\"2\",\"bob\""),
+            vec![row(&["id", "name"]), row(&["1", ""]), row(&["2", "bob"])]
+        );
+    }
+
+    #[test]
+    fn parse_csv_of_an_empty_result_has_no_rows() {
+        assert!(parse_csv("").is_empty());
+    }
+}
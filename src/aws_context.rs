@@ -0,0 +1,336 @@
+//! # aws_context
+//! This module holds the AWS configuration shared by the whole CLI.
+//! The configuration (profile, region, credentials) is loaded once when the CLI starts and every
+//! service client is created once per region and reused, including inside polling loops.
+//!
+//! Endpoints can be pointed to a local AWS emulator (LocalStack, moto) with the standard
+//! `AWS_ENDPOINT_URL` and `AWS_ENDPOINT_URL_<SERVICE>` environment variables, or with the
+//! endpoint keys of the config file.
+use crate::aws_credentials::{self, RoleOptions};
+use crate::cache::Cache;
+use crate::error::{Error, Result, ResultExt};
+use crate::retry;
+use aws_config::environment::region::EnvironmentVariableRegionProvider;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::ConfigLoader;
+use aws_sdk_athena::Client as AthenaClient;
+use aws_sdk_cloudwatchlogs::Client as CloudWatchLogsClient;
+use aws_sdk_ec2::Client as Ec2Client;
+use aws_sdk_glue::Client as GlueClient;
+use aws_sdk_iam::Client as IamClient;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sts::Client as StsClient;
+use aws_types::region::Region;
+use aws_types::SdkConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter};
+use tokio::sync::OnceCell;
+
+/// AWS services used by the CLI and the worker. The name is the suffix of the
+/// AWS_ENDPOINT_URL_<SERVICE> environment variable overriding the endpoint of the service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Display, EnumIter)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum Service {
+    Glue,
+    Ec2,
+    Iam,
+    Sts,
+    CloudwatchLogs,
+    S3,
+    Athena,
+}
+
+/// Options of the AWS environment the CLI runs against
+#[derive(Default)]
+pub struct AwsContextOptions {
+    /// Named profile of the AWS config and credentials files
+    pub profile: Option<String>,
+    /// Region taking precedence over AWS_REGION
+    pub region: Option<String>,
+    /// Region used when neither `region` nor AWS_REGION is set
+    pub default_region: Option<String>,
+    /// Regions to search for Glue databases, all enabled regions when `None`
+    pub regions: Option<Vec<String>>,
+    /// Role to assume for all calls
+    pub role: Option<RoleOptions>,
+    /// Do not launch an EC2 instance, simulate the worker instead. Used with AWS emulators.
+    pub simulate_instance: bool,
+    /// Ignore the cached discovery results
+    pub refresh: bool,
+    /// Endpoint overrides of the config file, the environment variables take precedence
+    pub endpoint_urls: HashMap<Service, String>,
+}
+
+/// Clients of one AWS service keyed by region
+type ClientCache<T> = Mutex<HashMap<String, T>>;
+
+/// Shared AWS configuration and client cache, cheap to clone
+#[derive(Clone)]
+pub struct AwsContext {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: SdkConfig,
+    endpoint_urls: HashMap<Service, String>,
+    regions: Option<Vec<String>>,
+    simulate_instance: bool,
+    cache: Cache,
+    account_id: OnceCell<String>,
+    glue: ClientCache<GlueClient>,
+    ec2: ClientCache<Ec2Client>,
+    iam: ClientCache<IamClient>,
+    sts: ClientCache<StsClient>,
+    cloudwatch_logs: ClientCache<CloudWatchLogsClient>,
+    s3: ClientCache<S3Client>,
+    athena: ClientCache<AthenaClient>,
+}
+
+/// Returns the cached client for the region or creates it with the given function
+fn get_or_create<T: Clone>(cache: &ClientCache<T>, region: &str, create: impl FnOnce() -> T) -> T {
+    cache
+        .lock()
+        .unwrap()
+        .entry(region.to_string())
+        .or_insert_with(create)
+        .clone()
+}
+
+impl AwsContext {
+    /// Loads the AWS configuration from the environment (AWS_PROFILE, AWS_REGION, CloudShell credentials)
+    /// and assumes the role if one is given
+    pub async fn new(options: AwsContextOptions) -> Result<Self> {
+        let endpoint_urls = load_endpoint_urls(options.endpoint_urls);
+        let mut profile_region = aws_config::default_provider::region::Builder::default();
+        if let Some(profile) = &options.profile {
+            profile_region = profile_region.profile_name(profile);
+        }
+        let region_provider = RegionProviderChain::first_try(options.region.map(Region::new))
+            .or_else(EnvironmentVariableRegionProvider::new())
+            .or_else(options.default_region.map(Region::new))
+            .or_else(profile_region.build())
+            .or_else("us-east-1");
+        let profile = options.profile.as_deref();
+        let mut loader = config_loader(profile).region(region_provider);
+        if let Some(role) = options.role {
+            let base_config = loader.load().await;
+            let sts_endpoint_url = endpoint_urls.get(&Service::Sts).cloned();
+            loader = config_loader(profile)
+                .region(base_config.region().cloned())
+                .credentials_provider(
+                    aws_credentials::assume_role(&base_config, sts_endpoint_url, role).await?,
+                );
+        }
+        let config = loader.load().await;
+
+        Ok(AwsContext {
+            inner: Arc::new(Inner {
+                config,
+                endpoint_urls,
+                regions: options.regions,
+                simulate_instance: options.simulate_instance,
+                cache: Cache::new(options.refresh),
+                account_id: OnceCell::new(),
+                glue: Mutex::default(),
+                ec2: Mutex::default(),
+                iam: Mutex::default(),
+                sts: Mutex::default(),
+                cloudwatch_logs: Mutex::default(),
+                s3: Mutex::default(),
+                athena: Mutex::default(),
+            }),
+        })
+    }
+
+    /// Region of the environment, used for calls that are not tied to the region of the data
+    pub fn default_region(&self) -> String {
+        self.inner.config.region().unwrap().to_string()
+    }
+
+    /// Regions to search for Glue databases, `None` searches all enabled regions
+    pub fn regions(&self) -> Option<&Vec<String>> {
+        self.inner.regions.as_ref()
+    }
+
+    /// Returns the endpoint override of the service, if any
+    pub fn endpoint_url(&self, service: Service) -> Option<&String> {
+        self.inner.endpoint_urls.get(&service)
+    }
+
+    pub fn simulate_instance(&self) -> bool {
+        self.inner.simulate_instance
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.inner.cache
+    }
+
+    /// Account id of the credentials, fetched once from STS in the region of the environment
+    pub async fn account_id(&self) -> Result<String> {
+        let action = "get the account id of the credentials";
+        self.inner
+            .account_id
+            .get_or_try_init(|| async {
+                self.sts(&self.default_region())
+                    .get_caller_identity()
+                    .send()
+                    .await
+                    .context(action)?
+                    .account
+                    .ok_or_else(|| Error::unexpected_response(action, "account"))
+            })
+            .await
+            .cloned()
+    }
+
+    pub fn glue(&self, region: &str) -> GlueClient {
+        get_or_create(&self.inner.glue, region, || {
+            let mut config = aws_sdk_glue::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::Glue).cloned());
+            let config = config.build();
+            GlueClient::from_conf(config)
+        })
+    }
+
+    pub fn ec2(&self, region: &str) -> Ec2Client {
+        get_or_create(&self.inner.ec2, region, || {
+            let mut config = aws_sdk_ec2::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::Ec2).cloned());
+            let config = config.build();
+            Ec2Client::from_conf(config)
+        })
+    }
+
+    /// IAM is a global service, the region only selects the partition endpoint
+    pub fn iam(&self, region: &str) -> IamClient {
+        get_or_create(&self.inner.iam, region, || {
+            let mut config = aws_sdk_iam::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::Iam).cloned());
+            let config = config.build();
+            IamClient::from_conf(config)
+        })
+    }
+
+    pub fn sts(&self, region: &str) -> StsClient {
+        get_or_create(&self.inner.sts, region, || {
+            let mut config = aws_sdk_sts::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::Sts).cloned());
+            let config = config.build();
+            StsClient::from_conf(config)
+        })
+    }
+
+    pub fn cloudwatch_logs(&self, region: &str) -> CloudWatchLogsClient {
+        get_or_create(&self.inner.cloudwatch_logs, region, || {
+            let mut config = aws_sdk_cloudwatchlogs::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::CloudwatchLogs).cloned());
+            let config = config.build();
+            CloudWatchLogsClient::from_conf(config)
+        })
+    }
+
+    /// Emulators serve buckets under the endpoint path, not as subdomains
+    pub fn s3(&self, region: &str) -> S3Client {
+        get_or_create(&self.inner.s3, region, || {
+            let mut config = aws_sdk_s3::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::S3).cloned());
+            config.set_force_path_style(self.endpoint_url(Service::S3).map(|_| true));
+            let config = config.build();
+            S3Client::from_conf(config)
+        })
+    }
+
+    pub fn athena(&self, region: &str) -> AthenaClient {
+        get_or_create(&self.inner.athena, region, || {
+            let mut config = aws_sdk_athena::config::Builder::from(&self.inner.config)
+                .region(Region::new(region.to_string()));
+            config.set_endpoint_url(self.endpoint_url(Service::Athena).cloned());
+            let config = config.build();
+            AthenaClient::from_conf(config)
+        })
+    }
+}
+
+/// Returns the ARN partition of the region, e.g. `aws-cn` for the China regions
+pub fn arn_partition(region: &str) -> &'static str {
+    if region.starts_with("cn-") {
+        "aws-cn"
+    } else if region.starts_with("us-gov-") {
+        "aws-us-gov"
+    } else if region.starts_with("us-isob-") {
+        "aws-iso-b"
+    } else if region.starts_with("us-iso-") {
+        "aws-iso"
+    } else {
+        "aws"
+    }
+}
+
+/// Returns a loader of the AWS configuration with the settings shared by all clients
+fn config_loader(profile: Option<&str>) -> ConfigLoader {
+    let loader = aws_config::from_env().retry_config(retry::retry_config());
+    match profile {
+        Some(profile) => loader.profile_name(profile),
+        None => loader,
+    }
+}
+
+/// Reads the endpoint overrides from AWS_ENDPOINT_URL_<SERVICE>, falling back to AWS_ENDPOINT_URL
+/// and then to the overrides of the config file
+fn load_endpoint_urls(mut configured: HashMap<Service, String>) -> HashMap<Service, String> {
+    let global_endpoint_url = std::env::var("AWS_ENDPOINT_URL").ok();
+    Service::iter()
+        .filter_map(|service| {
+            std::env::var(format!("AWS_ENDPOINT_URL_{}", service))
+                .ok()
+                .or_else(|| global_endpoint_url.clone())
+                .or_else(|| configured.remove(&service))
+                .map(|endpoint_url| (service, endpoint_url))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_follows_the_region() {
+        assert_eq!(arn_partition("eu-west-1"), "aws");
+        assert_eq!(arn_partition("cn-northwest-1"), "aws-cn");
+        assert_eq!(arn_partition("us-gov-west-1"), "aws-us-gov");
+        assert_eq!(arn_partition("us-iso-east-1"), "aws-iso");
+        assert_eq!(arn_partition("us-isob-east-1"), "aws-iso-b");
+    }
+
+    #[test]
+    fn environment_endpoints_take_precedence_over_the_config_file() {
+        // the only test reading the endpoint variables, so it can set them
+        std::env::remove_var("AWS_ENDPOINT_URL");
+        std::env::set_var("AWS_ENDPOINT_URL_ATHENA", "http://localhost:4567");
+        let configured = HashMap::from([
+            (Service::Athena, "http://localhost:4566".to_string()),
+            (Service::S3, "http://localhost:9000".to_string()),
+        ]);
+        let endpoint_urls = load_endpoint_urls(configured.clone());
+        assert_eq!(endpoint_urls[&Service::Athena], "http://localhost:4567");
+        assert_eq!(endpoint_urls[&Service::S3], "http://localhost:9000");
+        assert!(!endpoint_urls.contains_key(&Service::Glue));
+
+        std::env::set_var("AWS_ENDPOINT_URL", "http://localhost:4568");
+        let endpoint_urls = load_endpoint_urls(configured);
+        assert_eq!(endpoint_urls[&Service::Athena], "http://localhost:4567");
+        assert_eq!(endpoint_urls[&Service::S3], "http://localhost:4568");
+        assert_eq!(endpoint_urls[&Service::Glue], "http://localhost:4568");
+        std::env::remove_var("AWS_ENDPOINT_URL");
+        std::env::remove_var("AWS_ENDPOINT_URL_ATHENA");
+    }
+}
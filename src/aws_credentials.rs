@@ -0,0 +1,137 @@
+//! # aws_credentials
+//! This module provides assumed role credentials for the AWS clients of the CLI.
+//! When a role is given the CLI assumes it and refreshes the temporary credentials before they expire,
+//! so long running jobs keep working after the first session ends.
+use crate::PROJECT_NAME;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_credential_types::provider::{self, error::CredentialsError, future, ProvideCredentials};
+use aws_credential_types::Credentials;
+use aws_sdk_sts::Client as StsClient;
+use aws_types::SdkConfig;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+/// Credentials are refreshed when they are closer than this to their expiry
+const REFRESH_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Lifetime of the MFA session used to assume the role again on refresh
+const MFA_SESSION_SECONDS: i32 = 12 * 60 * 60;
+
+/// Role to assume for all AWS calls of the CLI
+pub struct RoleOptions {
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    pub mfa_serial: Option<String>,
+    pub mfa_token: Option<String>,
+}
+
+/// Provider that assumes a role and keeps the temporary credentials until they are about to expire
+#[derive(Debug)]
+struct AssumedRoleProvider {
+    sts: StsClient,
+    role_arn: String,
+    external_id: Option<String>,
+    credentials: Mutex<Option<Credentials>>,
+}
+
+impl AssumedRoleProvider {
+    async fn credentials(&self) -> provider::Result {
+        let mut cached = self.credentials.lock().await;
+        if let Some(credentials) = cached.as_ref() {
+            let is_fresh = credentials
+                .expiry()
+                .map(|expiry| expiry > SystemTime::now() + REFRESH_WINDOW)
+                .unwrap_or(true);
+            if is_fresh {
+                return Ok(credentials.clone());
+            }
+        }
+
+        let response = self
+            .sts
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(PROJECT_NAME)
+            .set_external_id(self.external_id.clone())
+            .send()
+            .await
+            .map_err(CredentialsError::provider_error)?;
+        let credentials = to_credentials(response.credentials(), "AssumedRoleProvider")?;
+        *cached = Some(credentials.clone());
+        Ok(credentials)
+    }
+}
+
+impl ProvideCredentials for AssumedRoleProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.credentials())
+    }
+}
+
+/// Converts temporary credentials returned by STS
+fn to_credentials(
+    credentials: Option<&aws_sdk_sts::model::Credentials>,
+    provider_name: &'static str,
+) -> provider::Result {
+    let credentials = credentials
+        .ok_or_else(|| CredentialsError::not_loaded("STS did not return credentials"))?;
+    Ok(Credentials::new(
+        credentials.access_key_id().unwrap_or_default(),
+        credentials.secret_access_key().unwrap_or_default(),
+        credentials.session_token().map(|token| token.to_string()),
+        credentials
+            .expiration()
+            .and_then(|expiration| SystemTime::try_from(*expiration).ok()),
+        provider_name,
+    ))
+}
+
+/// Returns a provider of credentials for the given role, built from the base configuration credentials.
+/// With MFA a session token is requested first, the role is then assumed with the MFA session
+/// which lets the credentials be refreshed without asking for a new token code.
+pub async fn assume_role(
+    base_config: &SdkConfig,
+    sts_endpoint_url: Option<String>,
+    options: RoleOptions,
+) -> Result<SharedCredentialsProvider, CredentialsError> {
+    let sts_config = || {
+        let mut sts_config = aws_sdk_sts::config::Builder::from(base_config);
+        sts_config.set_endpoint_url(sts_endpoint_url.clone());
+        sts_config
+    };
+    let base_sts = StsClient::from_conf(sts_config().build());
+
+    let sts = match (&options.mfa_serial, &options.mfa_token) {
+        (Some(mfa_serial), Some(mfa_token)) => {
+            let session = base_sts
+                .get_session_token()
+                .serial_number(mfa_serial)
+                .token_code(mfa_token)
+                .duration_seconds(MFA_SESSION_SECONDS)
+                .send()
+                .await
+                .map_err(CredentialsError::provider_error)?;
+            let mfa_credentials = to_credentials(session.credentials(), "MfaSession")?;
+            StsClient::from_conf(sts_config().credentials_provider(mfa_credentials).build())
+        }
+        (Some(_), None) => {
+            return Err(CredentialsError::invalid_configuration(
+                "an MFA token code is required when an MFA device is given",
+            ))
+        }
+        _ => base_sts,
+    };
+
+    let provider = AssumedRoleProvider {
+        sts,
+        role_arn: options.role_arn,
+        external_id: options.external_id,
+        credentials: Mutex::new(None),
+    };
+    // fail early if the role cannot be assumed
+    provider.credentials().await?;
+
+    Ok(SharedCredentialsProvider::new(provider))
+}
@@ -0,0 +1,219 @@
+//! # bundle
+//! `synth_table bundle` downloads the pinned Python packages of the worker into a wheelhouse,
+//! uploads it to S3 as a tar.gz archive named after its SHA-256 and records the location and the
+//! hash in the config file. Jobs then install their packages from the archive instead of PyPI,
+//! which makes runs reproducible and faster, and works in subnets without internet access.
+use crate::aws_context::AwsContext;
+use crate::cli::{Architecture, BundleArgs};
+use crate::config::{self, Config};
+use crate::error::{Error, Result, ResultExt};
+use crate::get_glue_data::split_s3_location;
+use crate::get_processing_job::REQUIREMENTS;
+use aws_sdk_s3::types::ByteStream;
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::process::Command;
+
+/// Python version of the worker instance the wheels are downloaded for
+const WORKER_PYTHON_VERSION: &str = "3.11";
+/// Platform tags of the wheels that install on the glibc 2.34 of Amazon Linux 2023, without the
+/// machine. pip matches the tags exactly, most packages publish manylinux2014 or 2_28 wheels.
+const WORKER_PLATFORMS: [&str; 3] = ["manylinux2014", "manylinux_2_28", "manylinux_2_34"];
+
+/// Platform tags of the wheels of workers of the architecture
+fn worker_platforms(architecture: Architecture) -> Vec<String> {
+    let machine = match architecture {
+        Architecture::X86_64 => "x86_64",
+        Architecture::Arm64 => "aarch64",
+    };
+    WORKER_PLATFORMS
+        .iter()
+        .map(|platform| format!("{}_{}", platform, machine))
+        .collect()
+}
+
+/// Builds the wheelhouse, uploads it and records it in the config file
+pub async fn run_bundle(
+    ctx: &AwsContext,
+    args: &BundleArgs,
+    architecture: Architecture,
+) -> Result<()> {
+    if !args.destination.starts_with("s3://") {
+        return Err(Error::Config(format!(
+            "{} is not an S3 prefix, for example s3://my-bucket/synth_table/",
+            args.destination
+        )));
+    }
+    let dir = std::env::temp_dir().join(format!("synth_table-bundle-{}", std::process::id()));
+    let result = build_and_upload(ctx, &dir, &args.destination, architecture).await;
+    let _ = std::fs::remove_dir_all(&dir);
+    let (location, sha256) = result?;
+
+    let (location_key, sha256_key) = config::wheelhouse_keys(architecture);
+    Config::set(location_key, &location)?;
+    Config::set(sha256_key, &sha256)?;
+    println!(
+        "{}",
+        format!(
+            "Wheelhouse uploaded to {}\nSHA-256 {}\nBoth are recorded in the config file, \
+            {} jobs install their packages from the wheelhouse from now on.",
+            location,
+            sha256,
+            architecture.as_str()
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Returns the S3 location and the SHA-256 of the uploaded archive
+async fn build_and_upload(
+    ctx: &AwsContext,
+    dir: &Path,
+    destination: &str,
+    architecture: Architecture,
+) -> Result<(String, String)> {
+    let to_bundle_error = |err: std::io::Error| {
+        Error::Bundle(format!(
+            "Could not write the wheelhouse to {}: {}",
+            dir.display(),
+            err
+        ))
+    };
+    // the requirements travel in the archive, the worker installs exactly what was downloaded
+    let wheels = dir.join("wheels");
+    std::fs::create_dir_all(&wheels).map_err(to_bundle_error)?;
+    let requirements = wheels.join("requirements.txt");
+    std::fs::write(&requirements, REQUIREMENTS).map_err(to_bundle_error)?;
+
+    println!(
+        "Downloading the Python packages of {} workers ...",
+        architecture.as_str()
+    );
+    let platforms = worker_platforms(architecture);
+    let mut args: Vec<&std::ffi::OsStr> = vec![
+        "-m".as_ref(),
+        "pip".as_ref(),
+        "download".as_ref(),
+        "--only-binary=:all:".as_ref(),
+    ];
+    for platform in &platforms {
+        args.push("--platform".as_ref());
+        args.push(platform.as_ref());
+    }
+    args.extend::<[&std::ffi::OsStr; 8]>([
+        "--python-version".as_ref(),
+        WORKER_PYTHON_VERSION.as_ref(),
+        "--implementation".as_ref(),
+        "cp".as_ref(),
+        "--dest".as_ref(),
+        wheels.as_os_str(),
+        "--requirement".as_ref(),
+        requirements.as_os_str(),
+    ]);
+    run_tool("python3", &args).await?;
+    let archive = dir.join("wheelhouse.tar.gz");
+    run_tool(
+        "tar",
+        &[
+            "-czf".as_ref(),
+            archive.as_os_str(),
+            "-C".as_ref(),
+            wheels.as_os_str(),
+            ".".as_ref(),
+        ],
+    )
+    .await?;
+
+    let sha256 = hex::encode(Sha256::digest(
+        std::fs::read(&archive).map_err(to_bundle_error)?,
+    ));
+    // archives are never overwritten, a job always gets the packages it was launched with
+    let location = format!(
+        "{}/wheelhouse-{}-{}.tar.gz",
+        destination.trim_end_matches('/'),
+        architecture.as_str(),
+        &sha256[..12]
+    );
+    println!("Uploading the wheelhouse to {} ...", location);
+    upload(ctx, &archive, &location).await?;
+    Ok((location, sha256))
+}
+
+/// Uploads the file to the S3 location, through the region of its bucket
+async fn upload(ctx: &AwsContext, path: &Path, location: &str) -> Result<()> {
+    let action = format!("upload the wheelhouse to {}", location);
+    let (bucket, key) = split_s3_location(location).ok_or_else(|| {
+        Error::Config(format!(
+            "{} is not an S3 prefix, for example s3://my-bucket/synth_table/",
+            location
+        ))
+    })?;
+    // buckets in us-east-1 have no location constraint, "EU" is the legacy name of eu-west-1
+    let bucket_region = match ctx
+        .s3(&ctx.default_region())
+        .get_bucket_location()
+        .bucket(bucket)
+        .send()
+        .await
+        .context(&action)?
+        .location_constraint()
+        .map(|constraint| constraint.as_str())
+    {
+        None | Some("") => "us-east-1".to_string(),
+        Some("EU") => "eu-west-1".to_string(),
+        Some(region) => region.to_string(),
+    };
+    let body = ByteStream::from_path(path)
+        .await
+        .map_err(|err| Error::Bundle(format!("Could not read {}: {}", path.display(), err)))?;
+    ctx.s3(&bucket_region)
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .body(body)
+        .send()
+        .await
+        .context(&action)?;
+    Ok(())
+}
+
+/// Runs a local tool, its output is shown to the user
+async fn run_tool(program: &str, args: &[&std::ffi::OsStr]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .await
+        .map_err(|err| Error::Bundle(format!("Could not run {}: {}", program, err)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Bundle(format!("{} failed with {}", program, status)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_platforms_match_the_architecture() {
+        assert_eq!(
+            worker_platforms(Architecture::X86_64),
+            [
+                "manylinux2014_x86_64",
+                "manylinux_2_28_x86_64",
+                "manylinux_2_34_x86_64"
+            ]
+        );
+        assert_eq!(
+            worker_platforms(Architecture::Arm64),
+            [
+                "manylinux2014_aarch64",
+                "manylinux_2_28_aarch64",
+                "manylinux_2_34_aarch64"
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! # cache
+//! On-disk cache of the discovery results (databases, tables, subnets), so repeat runs of the
+//! wizard start instantly. Entries are kept per account under `~/.synth_table/cache/<account id>`
+//! and expire after [`CACHE_TTL`]; `--refresh` ignores them and stores fresh results.
+//!
+//! Each entry is a text file with a version header and one record per line, fields separated by tabs.
+use crate::aws_context::AwsContext;
+use crate::config::config_dir;
+use crate::error::Result;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Age after which cached discovery results are fetched again
+const CACHE_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+/// First line of every cache file, bumped when a record layout changes
+const CACHE_HEADER: &str = "synth_table cache v4";
+
+/// A value that can be stored as one line of a cache file
+pub trait CacheRecord: Sized {
+    fn to_fields(&self) -> Vec<String>;
+    /// Returns `None` when the fields do not match the record layout
+    fn from_fields(fields: &[String]) -> Option<Self>;
+}
+
+pub struct Cache {
+    dir: Option<PathBuf>,
+    refresh: bool,
+}
+
+impl Cache {
+    /// With `refresh` cached entries are ignored, fresh results still replace them
+    pub fn new(refresh: bool) -> Self {
+        Cache {
+            dir: config_dir().map(|dir| dir.join("cache")),
+            refresh,
+        }
+    }
+
+    fn path(&self, account_id: &str, key: &str) -> Option<PathBuf> {
+        // keys hold names of regions, catalogs and databases, keep them safe as file names
+        let file_name = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(account_id).join(file_name))
+    }
+
+    /// Returns the cached records, `None` if missing, expired, unreadable or refreshing
+    fn read<T: CacheRecord>(&self, account_id: &str, key: &str) -> Option<Vec<T>> {
+        if self.refresh {
+            return None;
+        }
+        let path = self.path(account_id, key)?;
+        let age = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+        if age > CACHE_TTL {
+            return None;
+        }
+        let contents = std::fs::read_to_string(path).ok()?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(CACHE_HEADER) {
+            return None;
+        }
+        lines
+            .map(|line| T::from_fields(&line.split('\t').map(unescape).collect::<Vec<_>>()))
+            .collect()
+    }
+
+    /// Stores the records. The cache is best effort, failures to write it are ignored.
+    fn write<T: CacheRecord>(&self, account_id: &str, key: &str, records: &[T]) {
+        let Some(path) = self.path(account_id, key) else {
+            return;
+        };
+        let mut contents = format!("{}\n", CACHE_HEADER);
+        for record in records {
+            let fields = record
+                .to_fields()
+                .iter()
+                .map(|field| escape(field))
+                .collect::<Vec<_>>();
+            contents.push_str(&fields.join("\t"));
+            contents.push('\n');
+        }
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = std::fs::write(path, contents);
+    }
+}
+
+/// Returns the cached records for the key in the account of the credentials,
+/// or loads them with `load` and caches them
+pub async fn get_or_load<T, F, Fut>(ctx: &AwsContext, key: &str, load: F) -> Result<Vec<T>>
+where
+    T: CacheRecord,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Vec<T>>>,
+{
+    let account_id = ctx.account_id().await?;
+    if let Some(records) = ctx.cache().read(&account_id, key) {
+        return Ok(records);
+    }
+    let records = load().await?;
+    ctx.cache().write(&account_id, key, &records);
+    Ok(records)
+}
+
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => unescaped.push('\t'),
+                Some('n') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => {}
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[derive(Debug, PartialEq)]
+    struct Record(String, String);
+
+    impl CacheRecord for Record {
+        fn to_fields(&self) -> Vec<String> {
+            vec![self.0.clone(), self.1.clone()]
+        }
+        fn from_fields(fields: &[String]) -> Option<Self> {
+            match fields {
+                [first, second] => Some(Record(first.clone(), second.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    fn cache(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!(
+            "synth_table-cache-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Cache {
+            dir: Some(dir),
+            refresh: false,
+        }
+    }
+
+    #[test]
+    fn escape_round_trips_separators() {
+        for field in [
+            "plain",
+            "tab\there",
+            "line\nbreak",
+            "back\\slash",
+            "\\t",
+            "",
+            "\\",
+        ] {
+            assert_eq!(unescape(&escape(field)), field);
+        }
+        assert!(!escape("a\tb\nc").contains(['\t', '\n']));
+    }
+
+    #[test]
+    fn unescape_drops_a_dangling_backslash() {
+        assert_eq!(unescape("end\\"), "end");
+    }
+
+    #[test]
+    fn write_then_read_returns_the_records() {
+        let cache = cache("round-trip");
+        let records = vec![
+            Record("db\twith tab".to_string(), "line\nbreak".to_string()),
+            Record("".to_string(), "s3://bucket/prefix\\".to_string()),
+        ];
+        cache.write("123456789012", "tables-eu-west-1", &records);
+        assert_eq!(
+            cache.read::<Record>("123456789012", "tables-eu-west-1"),
+            Some(records)
+        );
+    }
+
+    #[test]
+    fn missing_entry_is_not_cached() {
+        let cache = cache("missing");
+        assert_eq!(cache.read::<Record>("123456789012", "databases"), None);
+    }
+
+    #[test]
+    fn refresh_ignores_the_entry() {
+        let mut cache = cache("refresh");
+        cache.write(
+            "123456789012",
+            "databases",
+            &[Record("a".into(), "b".into())],
+        );
+        cache.refresh = true;
+        assert_eq!(cache.read::<Record>("123456789012", "databases"), None);
+    }
+
+    #[test]
+    fn expired_entry_is_not_cached() {
+        let cache = cache("expired");
+        cache.write(
+            "123456789012",
+            "databases",
+            &[Record("a".into(), "b".into())],
+        );
+        let path = cache.path("123456789012", "databases").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - CACHE_TTL - Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(cache.read::<Record>("123456789012", "databases"), None);
+    }
+
+    #[test]
+    fn entry_of_another_version_is_not_cached() {
+        let cache = cache("old-version");
+        let path = cache.path("123456789012", "databases").unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "synth_table cache v3\na\tb\n").unwrap();
+        assert_eq!(cache.read::<Record>("123456789012", "databases"), None);
+    }
+
+    #[test]
+    fn corrupt_entry_is_not_cached() {
+        let cache = cache("corrupt");
+        let path = cache.path("123456789012", "databases").unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, format!("{}\na\tb\nonly one field\n", CACHE_HEADER)).unwrap();
+        assert_eq!(cache.read::<Record>("123456789012", "databases"), None);
+    }
+
+    #[test]
+    fn keys_are_safe_file_names() {
+        let cache = cache("keys");
+        let path = cache
+            .path("123456789012", "tables-../other/db name")
+            .unwrap();
+        assert_eq!(path.file_name().unwrap(), "tables-.._other_db_name");
+        assert!(path.starts_with(cache.dir.unwrap().join("123456789012")));
+    }
+}
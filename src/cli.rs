@@ -0,0 +1,161 @@
+//! # cli
+//! Command line options of the CLI. Everything else is asked for by the prompts.
+use aws_sdk_ec2::model::{InstanceType, VolumeType};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Guided wizard to generate synthetic copies of AWS Glue tables"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Named profile of the AWS config and credentials files
+    #[arg(long, env = "AWS_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Region of the AWS calls not tied to the data. Also the only region searched for
+    /// Glue databases unless --regions is given
+    #[arg(long)]
+    pub region: Option<String>,
+
+    /// Comma separated list of regions to search for Glue databases instead of all enabled regions
+    #[arg(long, value_delimiter = ',')]
+    pub regions: Option<Vec<String>>,
+
+    /// ARN of a role to assume for all AWS calls, e.g. to reach source data in another account
+    #[arg(long, env = "SYNTH_TABLE_ROLE_ARN")]
+    pub role_arn: Option<String>,
+
+    /// External ID required by the trust policy of the role
+    #[arg(long, requires = "role_arn")]
+    pub external_id: Option<String>,
+
+    /// ARN or serial number of the MFA device required to assume the role
+    #[arg(long, requires = "role_arn")]
+    pub mfa_serial: Option<String>,
+
+    /// Current MFA token code, asked for interactively when omitted
+    #[arg(long, requires = "mfa_serial")]
+    pub mfa_token: Option<String>,
+
+    /// CPU architecture of the worker. arm64 runs the job on Graviton instances, cheaper for
+    /// CPU-bound training. Also selects the wheels downloaded by the bundle command
+    #[arg(long, value_enum, default_value_t = Architecture::X86_64)]
+    pub arch: Architecture,
+
+    /// EC2 instance type of the job, e.g. m6i.2xlarge or m6g.2xlarge with --arch arm64. Chosen in
+    /// the wizard from a recommendation based on the size of the table when omitted
+    #[arg(long, value_parser = parse_instance_type)]
+    pub instance_type: Option<InstanceType>,
+
+    /// AMI to launch instead of the latest Amazon Linux 2023, as AMI id or resolve:ssm: parameter,
+    /// e.g. a hardened golden image. The image must provide dnf and Python 3.11
+    #[arg(long)]
+    pub ami: Option<String>,
+
+    /// Size in GiB of the root volume of the instance, within the limits of the volume type.
+    /// Sized from the size of the table when omitted
+    #[arg(long, value_parser = clap::value_parser!(i32).range(8..=16384))]
+    pub volume_size: Option<i32>,
+
+    /// EBS volume type of the root volume, st1 and sc1 cannot boot an instance
+    #[arg(long, value_parser = parse_volume_type, default_value = "gp3")]
+    pub volume_type: VolumeType,
+
+    /// KMS key to encrypt the root volume with, as key ID, key ARN or alias ARN.
+    /// The volume is encrypted with the default EBS key of the account when omitted
+    #[arg(long, conflicts_with = "unencrypted")]
+    pub kms_key_id: Option<String>,
+
+    /// Do not encrypt the root volume
+    #[arg(long)]
+    pub unencrypted: bool,
+
+    /// S3 location of a tar.gz archive of the Python wheels of the worker. The worker installs its
+    /// packages from it instead of PyPI, which subnets that only have VPC endpoints cannot reach
+    #[arg(long)]
+    pub wheelhouse: Option<String>,
+
+    /// SHA-256 of the wheelhouse, checked by the worker before installing from it
+    #[arg(long, requires = "wheelhouse")]
+    pub wheelhouse_sha256: Option<String>,
+
+    /// Run the job on spot capacity, cheaper but EC2 can reclaim the instance at any time
+    #[arg(long)]
+    pub spot: bool,
+
+    /// What to do when EC2 reclaims the spot instance before the job completes
+    #[arg(long, value_enum, default_value_t = OnInterruption::Relaunch)]
+    pub on_interruption: OnInterruption,
+
+    /// Do not launch an EC2 instance, simulate the worker instead.
+    /// Used to run the whole workflow against AWS emulators such as LocalStack
+    #[arg(long, env = "SYNTH_TABLE_SIMULATE_INSTANCE")]
+    pub simulate_instance: bool,
+
+    /// Ignore the cached databases, tables and subnets and fetch them again
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Download the pinned Python packages of the worker into a wheelhouse, upload it to S3 and
+    /// record it in the config file, so jobs install from it instead of PyPI
+    Bundle(BundleArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BundleArgs {
+    /// S3 prefix to upload the wheelhouse to, e.g. s3://my-bucket/synth_table/
+    pub destination: String,
+}
+
+/// CPU architecture of the worker instance
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Architecture {
+    /// Intel and AMD instances
+    #[value(name = "x86_64")]
+    X86_64,
+    /// AWS Graviton instances
+    Arm64,
+}
+
+impl Architecture {
+    /// Name of the architecture in EC2 and in the Amazon Linux AMI parameters
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Architecture::X86_64 => "x86_64",
+            Architecture::Arm64 => "arm64",
+        }
+    }
+}
+
+/// Handling of a spot interruption
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OnInterruption {
+    /// Run the job again on an on-demand instance
+    Relaunch,
+    /// Stop and report the job as interrupted
+    Stop,
+}
+
+fn parse_instance_type(value: &str) -> Result<InstanceType, String> {
+    match InstanceType::from(value) {
+        InstanceType::Unknown(_) => Err(format!("{} is not an EC2 instance type", value)),
+        instance_type => Ok(instance_type),
+    }
+}
+
+fn parse_volume_type(value: &str) -> Result<VolumeType, String> {
+    match VolumeType::from(value) {
+        VolumeType::Unknown(_) => Err(format!("{} is not an EBS volume type", value)),
+        VolumeType::St1 | VolumeType::Sc1 => {
+            Err(format!("{} volumes cannot be used as root volume", value))
+        }
+        volume_type => Ok(volume_type),
+    }
+}
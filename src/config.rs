@@ -0,0 +1,349 @@
+//! # config
+//! Defaults of the command line options, read from `~/.synth_table/config`.
+//! The file holds one `key = value` pair per line, lines starting with `#` are comments:
+//!
+//! ```text
+//! profile = analytics
+//! region = eu-west-1
+//! regions = eu-west-1, eu-central-1
+//! wheelhouse = s3://my-bucket/synth_table/wheelhouse-x86_64-0123456789ab.tar.gz
+//! wheelhouse_sha256 = 0123456789ab...
+//! wheelhouse_arm64 = s3://my-bucket/synth_table/wheelhouse-arm64-0123456789ab.tar.gz
+//! wheelhouse_arm64_sha256 = 0123456789ab...
+//! endpoint_url = http://localhost:4566
+//! endpoint_url_s3 = http://localhost:9000
+//! ```
+//!
+//! `synth_table bundle` records the wheelhouse it uploads in the file, wheels are built for one
+//! architecture so arm64 workers have their own keys.
+//!
+//! `endpoint_url` points every service to an AWS emulator, `endpoint_url_<service>` one service
+//! (glue, ec2, iam, sts, cloudwatch_logs, s3, athena).
+//!
+//! Command line options and the AWS_PROFILE, AWS_REGION and AWS_ENDPOINT_URL environment variables
+//! take precedence.
+use crate::aws_context::Service;
+use crate::cli::Architecture;
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use strum::IntoEnumIterator;
+
+/// Keys accepted in the config file
+const KEYS: [&str; 7] = [
+    "profile",
+    "region",
+    "regions",
+    "wheelhouse",
+    "wheelhouse_sha256",
+    "wheelhouse_arm64",
+    "wheelhouse_arm64_sha256",
+];
+
+/// Key of the endpoint override of every service
+const ENDPOINT_URL_KEY: &str = "endpoint_url";
+
+/// Key of the endpoint override of the service
+fn endpoint_url_key(service: Service) -> String {
+    format!(
+        "{}_{}",
+        ENDPOINT_URL_KEY,
+        service.to_string().to_lowercase()
+    )
+}
+
+/// Checks that the key is accepted in the config file
+fn is_key(key: &str) -> bool {
+    KEYS.contains(&key)
+        || key == ENDPOINT_URL_KEY
+        || Service::iter().any(|service| key == endpoint_url_key(service))
+}
+
+/// Keys of the wheelhouse of the architecture and of its SHA-256
+pub fn wheelhouse_keys(architecture: Architecture) -> (&'static str, &'static str) {
+    match architecture {
+        Architecture::X86_64 => ("wheelhouse", "wheelhouse_sha256"),
+        Architecture::Arm64 => ("wheelhouse_arm64", "wheelhouse_arm64_sha256"),
+    }
+}
+
+/// Directory holding the config file and the files the CLI keeps between runs
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".synth_table"))
+}
+
+fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config"))
+}
+
+/// File with the subnet last chosen in each region, one `region = subnet id` pair per line
+fn last_subnets_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("last_subnets"))
+}
+
+/// Subnet last chosen in the region, offered first the next time
+pub fn last_subnet(region: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(last_subnets_path()?).ok()?;
+    contents.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == region).then(|| value.trim().to_string())
+    })
+}
+
+/// Remembers the subnet chosen in the region
+pub fn set_last_subnet(region: &str, subnet_id: &str) -> Result<()> {
+    let Some(path) = last_subnets_path() else {
+        return Err(Error::Config(
+            "HOME is not set, the last subnet cannot be written".to_string(),
+        ));
+    };
+    write_value(&path, region, subnet_id)
+}
+
+/// Stores the `key = value` pair in the file, replacing the previous value of the key.
+/// The other lines are kept as they are.
+fn write_value(path: &Path, key: &str, value: &str) -> Result<()> {
+    let to_config_error =
+        |err: std::io::Error| Error::Config(format!("Could not write {}: {}", path.display(), err));
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(to_config_error(err)),
+    };
+
+    let mut lines = contents
+        .lines()
+        .filter(|line| {
+            line.split_once('=')
+                .is_none_or(|(line_key, _)| line_key.trim() != key)
+        })
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    lines.push(format!("{} = {}", key, value));
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(to_config_error)?;
+    }
+    std::fs::write(path, format!("{}\n", lines.join("\n"))).map_err(to_config_error)
+}
+
+#[derive(Default)]
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    /// Reads the config file, a missing file gives an empty config
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => {
+                return Err(Error::Config(format!(
+                    "Could not read {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+        };
+        Config::parse(&path, &contents)
+    }
+
+    /// Parses the contents of the config file at the path
+    fn parse(path: &Path, contents: &str) -> Result<Self> {
+        let mut values = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) if is_key(key.trim()) => {
+                    values.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                _ => {
+                    return Err(Error::Config(format!(
+                        "Invalid line {} in {}, expected one of {}, {} or {}_<service> followed by = and a value",
+                        number + 1,
+                        path.display(),
+                        KEYS.join(", "),
+                        ENDPOINT_URL_KEY,
+                        ENDPOINT_URL_KEY
+                    )))
+                }
+            }
+        }
+        Ok(Config { values })
+    }
+
+    pub fn profile(&self) -> Option<String> {
+        self.values.get("profile").cloned()
+    }
+
+    pub fn region(&self) -> Option<String> {
+        self.values.get("region").cloned()
+    }
+
+    /// S3 location of the Python packages of workers of the architecture, for subnets without
+    /// internet access
+    pub fn wheelhouse(&self, architecture: Architecture) -> Option<String> {
+        self.values.get(wheelhouse_keys(architecture).0).cloned()
+    }
+
+    /// SHA-256 of the wheelhouse, recorded by `synth_table bundle`
+    pub fn wheelhouse_sha256(&self, architecture: Architecture) -> Option<String> {
+        self.values.get(wheelhouse_keys(architecture).1).cloned()
+    }
+
+    /// Stores the value in the config file, replacing the previous value of the key.
+    /// Comments and the other keys are kept as they are.
+    pub fn set(key: &str, value: &str) -> Result<()> {
+        let Some(path) = config_path() else {
+            return Err(Error::Config(
+                "HOME is not set, the config file cannot be written".to_string(),
+            ));
+        };
+        write_value(&path, key, value)
+    }
+
+    /// Endpoint overrides of the services, `endpoint_url_<service>` taking precedence over
+    /// `endpoint_url`
+    pub fn endpoint_urls(&self) -> HashMap<Service, String> {
+        Service::iter()
+            .filter_map(|service| {
+                self.values
+                    .get(&endpoint_url_key(service))
+                    .or_else(|| self.values.get(ENDPOINT_URL_KEY))
+                    .map(|endpoint_url| (service, endpoint_url.clone()))
+            })
+            .collect()
+    }
+
+    /// Regions to search for Glue databases
+    pub fn regions(&self) -> Option<Vec<String>> {
+        self.values.get("regions").map(|regions| {
+            regions
+                .split(',')
+                .map(|region| region.trim().to_string())
+                .filter(|region| !region.is_empty())
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<Config> {
+        Config::parse(Path::new("config"), contents)
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "synth_table-config-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("config")
+    }
+
+    #[test]
+    fn parse_reads_keys_and_skips_comments() {
+        let config = parse(
+            "# defaults\n\nprofile = analytics\n  region=eu-west-1  \nregions = eu-west-1, ,eu-central-1\n",
+        )
+        .unwrap();
+        assert_eq!(config.profile().as_deref(), Some("analytics"));
+        assert_eq!(config.region().as_deref(), Some("eu-west-1"));
+        assert_eq!(
+            config.regions(),
+            Some(vec!["eu-west-1".to_string(), "eu-central-1".to_string()])
+        );
+    }
+
+    #[test]
+    fn parse_keeps_the_last_value_of_a_key() {
+        let config = parse("region = eu-west-1\nregion = us-east-1\n").unwrap();
+        assert_eq!(config.region().as_deref(), Some("us-east-1"));
+    }
+
+    #[test]
+    fn parse_keeps_equal_signs_of_the_value() {
+        let config = parse("wheelhouse = s3://bucket/a=b/wheelhouse.tar.gz\n").unwrap();
+        assert_eq!(
+            config.wheelhouse(Architecture::X86_64).as_deref(),
+            Some("s3://bucket/a=b/wheelhouse.tar.gz")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys_and_lines_without_a_value() {
+        for contents in [
+            "profile = analytics\nprofiel = analytics\n",
+            "profile = analytics\nregion\n",
+        ] {
+            match parse(contents) {
+                Err(Error::Config(message)) => assert!(message.contains("line 2"), "{}", message),
+                _ => panic!("expected an invalid line in {:?}", contents),
+            }
+        }
+    }
+
+    #[test]
+    fn wheelhouse_is_read_per_architecture() {
+        let config = parse(
+            "wheelhouse = s3://bucket/x86_64.tar.gz\nwheelhouse_sha256 = 01\n\
+            wheelhouse_arm64 = s3://bucket/arm64.tar.gz\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.wheelhouse(Architecture::X86_64).as_deref(),
+            Some("s3://bucket/x86_64.tar.gz")
+        );
+        assert_eq!(
+            config.wheelhouse_sha256(Architecture::X86_64).as_deref(),
+            Some("01")
+        );
+        assert_eq!(
+            config.wheelhouse(Architecture::Arm64).as_deref(),
+            Some("s3://bucket/arm64.tar.gz")
+        );
+        assert_eq!(config.wheelhouse_sha256(Architecture::Arm64), None);
+    }
+
+    #[test]
+    fn service_endpoint_takes_precedence_over_the_global_one() {
+        let config = parse(
+            "endpoint_url = http://localhost:4566\nendpoint_url_s3 = http://localhost:9000\n",
+        )
+        .unwrap();
+        let endpoint_urls = config.endpoint_urls();
+        assert_eq!(endpoint_urls.len(), Service::iter().count());
+        assert_eq!(endpoint_urls[&Service::S3], "http://localhost:9000");
+        assert_eq!(endpoint_urls[&Service::Glue], "http://localhost:4566");
+        assert!(parse("endpoint_url_cloudwatch_logs = http://localhost:4566\n").is_ok());
+        assert!(parse("endpoint_url_dynamodb = http://localhost:4566\n").is_err());
+    }
+
+    #[test]
+    fn write_value_replaces_the_key_and_keeps_the_other_lines() {
+        let path = temp_file("write");
+        write_value(&path, "region", "eu-west-1").unwrap();
+        std::fs::write(
+            &path,
+            "# defaults\nregion = eu-west-1\nprofile = analytics\n",
+        )
+        .unwrap();
+        write_value(&path, "region", "us-east-1").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# defaults\nprofile = analytics\nregion = us-east-1\n"
+        );
+        let config = Config::parse(&path, &std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.region().as_deref(), Some("us-east-1"));
+    }
+}
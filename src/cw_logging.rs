@@ -3,14 +3,21 @@
 //! EC2 instance, python script and bash script all use the same logging group and log stream.
 //! The log group is called "SytheticData" and the log stream is the name of the AWS Glue table
 //! that is being processed.
+use crate::aws_context::AwsContext;
+use crate::error::{Result, ResultExt};
+use crate::retry::wait_until;
 use crate::PROJECT_NAME;
 use aws_sdk_cloudwatchlogs::model::InputLogEvent;
-use aws_sdk_cloudwatchlogs::{Client, Error, Region};
+use aws_sdk_cloudwatchlogs::Client;
 use chrono::Local;
+use std::time::Duration;
+
+/// Time CloudWatch logs gets to make a created log group and log stream visible
+const LOG_STREAM_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Create logger to send logs to cloudwatch from CLI
 pub struct CWLogSender {
-    region_name: String,
+    client: Client,
     log_group_name: String,
     log_stream_name: String,
 }
@@ -18,143 +25,111 @@ pub struct CWLogSender {
 /// Get cloudwatchlogs client for the region specified in the environment or default region
 impl CWLogSender {
     // Here we also create AWS client for cloudwatch logs as part of initialization
-    pub async fn new(region_name: String, log_stream_name: String) -> Self {
-        let _logging = set_up_cw_logging(PROJECT_NAME, &log_stream_name, &region_name)
-            .await
-            .expect("Could not set up logging");
+    pub async fn new(ctx: &AwsContext, region_name: &str, log_stream_name: String) -> Result<Self> {
+        let client = ctx.cloudwatch_logs(region_name);
+        set_up_cw_logging(&client, PROJECT_NAME, &log_stream_name).await?;
         let logger = CWLogSender {
-            region_name,
+            client,
             log_group_name: PROJECT_NAME.to_string(),
             log_stream_name,
         };
-        logger
-            .send_log("Setting up logging ...")
-            .await
-            .expect("Could not send log");
-        logger
+        logger.send_log("Setting up logging ...").await?;
+        Ok(logger)
     }
     /// Getting last log line from cloudwatch logs to provide feedback to user
-    pub async fn get_last_log_line(&self) -> Result<String, Error> {
-        let client = get_cloudwatchlogs_client(&self.region_name)
-            .await
-            .expect("Could not get client");
+    pub async fn get_last_log_line(&self) -> Result<String> {
         // get last log line
-        let last_log_line = client
+        let last_log_line = self
+            .client
             .get_log_events()
             .log_group_name(&self.log_group_name)
             .log_stream_name(&self.log_stream_name)
             .start_from_head(false)
             .send()
             .await
-            .expect("failed to get log events")
+            .context("read the job logs")?
             .events
-            .unwrap()
+            .unwrap_or_default()
             .iter()
             .rev()
             .take(1)
-            .map(|event| event.clone().message.unwrap())
+            .filter_map(|event| event.message.clone())
             .collect::<Vec<String>>()
             .join("");
         Ok(last_log_line)
     }
 
     /// Send log message to cloudwatch logs
-    pub async fn send_log(&self, message: &str) -> Result<(), Error> {
-        let client = get_cloudwatchlogs_client(&self.region_name)
-            .await
-            .expect("Could not get client");
-
+    pub async fn send_log(&self, message: &str) -> Result<()> {
         let message = InputLogEvent::builder()
             .message(message)
             .timestamp(Local::now().timestamp_millis())
             .build();
         // send log message to cloudwatch
-        let _response = &client
+        let _response = &self
+            .client
             .put_log_events()
             .log_group_name(&self.log_group_name)
             .log_stream_name(&self.log_stream_name)
             .log_events(message)
             .send()
             .await
-            .expect("Could not send log message");
+            .context("send a log message")?;
         Ok(())
     }
 }
 
 /// Create log group if it does not exist. Log group is called "SytheticData"
-async fn create_log_group(log_group_name: &str, region: &str) -> Result<(), Error> {
-    let client = get_cloudwatchlogs_client(region)
-        .await
-        .expect("Could not get client");
-
+async fn create_log_group(client: &Client, log_group_name: &str) -> Result<()> {
     let _response = &client
         .create_log_group()
         .log_group_name(log_group_name)
         .send()
         .await
-        .expect("Could not create log group");
+        .context("create the log group")?;
 
     Ok(())
 }
 
 /// Create log stream if it does not exist. Log stream is the name of the AWS Glue table
 async fn create_log_stream(
+    client: &Client,
     log_group_name: &str,
     log_stream_name: &str,
-    region: &str,
-) -> Result<(), Error> {
-    let client = get_cloudwatchlogs_client(region)
-        .await
-        .expect("Could not get client");
-
+) -> Result<()> {
     let _response = &client
         .create_log_stream()
         .log_group_name(log_group_name)
         .log_stream_name(log_stream_name)
         .send()
         .await
-        .expect("Could not create log stream");
+        .context("create the log stream")?;
 
     Ok(())
 }
 
-/// Set up cloudwatch client
-async fn get_cloudwatchlogs_client(region: &str) -> Result<Client, Error> {
-    let config = aws_config::from_env()
-        .region(Region::new(region.to_string()))
-        .load()
-        .await;
-    Ok(Client::new(&config))
-}
-
 /// Checks if log group allready exists
-async fn log_group_exists(log_group_name: &str, region: &str) -> Result<bool, Error> {
-    let client = get_cloudwatchlogs_client(region)
-        .await
-        .expect("Could not get client");
+async fn log_group_exists(client: &Client, log_group_name: &str) -> Result<bool> {
     // check if log group exists check for exact match
     let log_group_exists = client
         .describe_log_groups()
         .log_group_name_prefix(log_group_name)
         .send()
         .await
-        .expect("Could not get log groups")
+        .context("list the log groups")?
         .log_groups
-        .unwrap()
+        .unwrap_or_default()
         .iter()
-        .any(|log_group| log_group.log_group_name.as_ref().unwrap() == log_group_name);
+        .any(|log_group| log_group.log_group_name.as_deref() == Some(log_group_name));
     Ok(log_group_exists)
 }
 
 /// Checks if log stream exists
 async fn log_stream_exists(
+    client: &Client,
     log_group_name: &str,
     log_stream_name: &str,
-    region: &str,
-) -> Result<bool, Error> {
-    let client = get_cloudwatchlogs_client(region)
-        .await
-        .expect("Could not get client");
+) -> Result<bool> {
     // check if log stream exists check for exact match
     let log_stream_exists = client
         .describe_log_streams()
@@ -162,43 +137,36 @@ async fn log_stream_exists(
         .log_stream_name_prefix(log_stream_name)
         .send()
         .await
-        .expect("Could not get log streams")
+        .context("list the log streams")?
         .log_streams
-        .unwrap()
+        .unwrap_or_default()
         .iter()
-        .any(|log_stream| log_stream.log_stream_name.as_ref().unwrap() == log_stream_name);
+        .any(|log_stream| log_stream.log_stream_name.as_deref() == Some(log_stream_name));
     Ok(log_stream_exists)
 }
 
 /// Set up cloudwatch logging
 pub async fn set_up_cw_logging(
+    client: &Client,
     log_group_name: &str,
     log_stream_name: &str,
-    region: &str,
-) -> Result<(), Error> {
+) -> Result<()> {
     // check if log group exists is FALSE create log group
-    if !log_group_exists(log_group_name, region).await? {
-        create_log_group(log_group_name, region)
-            .await
-            .expect("Could not create log group");
+    if !log_group_exists(client, log_group_name).await? {
+        create_log_group(client, log_group_name).await?;
     }
     // check if log stream exists is FALSE create log stream
-    if !log_stream_exists(log_group_name, log_stream_name, region).await? {
-        create_log_stream(log_group_name, log_stream_name, region)
-            .await
-            .expect("Could not create log stream");
+    if !log_stream_exists(client, log_group_name, log_stream_name).await? {
+        create_log_stream(client, log_group_name, log_stream_name).await?;
     }
     // wait for log group and log stream to be ready
-    loop {
-        // check if log group and log stream exists
-        let is_log_stream_ready = log_group_exists(log_group_name, region).await?
-            && log_stream_exists(log_group_name, log_stream_name, region).await?;
-        if is_log_stream_ready {
-            break;
-        } else {
-            println!("Waiting for log group and log stream to be ready...");
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-        }
-    }
-    Ok(())
+    wait_until(
+        "the log group and log stream to be ready",
+        LOG_STREAM_TIMEOUT,
+        || async {
+            Ok(log_group_exists(client, log_group_name).await?
+                && log_stream_exists(client, log_group_name, log_stream_name).await?)
+        },
+    )
+    .await
 }
@@ -0,0 +1,371 @@
+//! # error
+//! Errors of the CLI. AWS SDK errors are classified by their error code so that the user gets
+//! guidance on how to fix the problem instead of a backtrace, and scripts get a distinct exit code
+//! for each kind of failure.
+use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_glue::types::SdkError;
+use aws_smithy_types::retry::{ErrorKind, ProvideErrorKind};
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The caller is not allowed to perform the action
+    AccessDenied { action: String, message: String },
+    /// A resource the CLI needs does not exist
+    NotFound(String),
+    /// AWS rejected the request because of its request rate
+    Throttled { action: String, message: String },
+    /// A service quota (instances, vCPUs, roles, ...) was reached
+    QuotaExceeded { action: String, message: String },
+    /// The VPC of the source data cannot run the worker instance
+    MisconfiguredVpc(String),
+    /// AWS could not be reached
+    Network { action: String, message: String },
+    /// No credentials, or the role could not be assumed
+    Credentials(String),
+    /// Any other error returned by an AWS service
+    Aws {
+        action: String,
+        code: String,
+        message: String,
+    },
+    /// The terminal prompt failed
+    Prompt(std::io::Error),
+    /// The user left a prompt without a selection
+    Cancelled,
+    /// The worker reported a failure
+    JobFailed(String),
+    /// EC2 reclaimed the spot instance of the job
+    Interrupted(String),
+    /// The wheelhouse could not be built
+    Bundle(String),
+    /// An eventually consistent change did not become visible in time
+    Timeout(String),
+    /// The config file cannot be read
+    Config(String),
+    /// The table is stored in a database the worker cannot read
+    UnsupportedSource(String),
+}
+
+impl Error {
+    /// Exit code of the CLI for the error, 1 and 2 are left to panics and argument errors
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::AccessDenied { .. } => 3,
+            Error::NotFound(_) => 4,
+            Error::Throttled { .. } => 5,
+            Error::QuotaExceeded { .. } => 6,
+            Error::MisconfiguredVpc(_) => 7,
+            Error::Network { .. } => 8,
+            Error::Credentials(_) => 9,
+            Error::Aws { .. } => 10,
+            Error::Prompt(_) => 11,
+            Error::Cancelled => 12,
+            Error::JobFailed(_) => 13,
+            Error::Timeout(_) => 14,
+            Error::Config(_) => 15,
+            Error::Interrupted(_) => 16,
+            Error::Bundle(_) => 17,
+            Error::UnsupportedSource(_) => 18,
+        }
+    }
+
+    /// What the user can do about the error
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            Error::AccessDenied { .. } => {
+                "Your credentials are missing a permission. Check the IAM policy of your user or role, \
+                or use --role-arn with a role that has the permissions listed in the README."
+            }
+            Error::NotFound(_) => {
+                "Check that the resource exists in the selected account and region. Databases, tables and \
+                subnets are cached for 12 hours, use --refresh to see recent changes."
+            }
+            Error::Throttled { .. } => {
+                "AWS is throttling the requests of your account. Wait a few minutes and run the CLI again."
+            }
+            Error::QuotaExceeded { .. } => {
+                "A service quota of your account was reached. Free up resources or request a quota \
+                increase in the Service Quotas console, then run the CLI again."
+            }
+            Error::MisconfiguredVpc(_) => {
+                "The job runs in a private subnet of the region of your data. Create a private subnet \
+                with available IP addresses and a route to a NAT gateway, or VPC endpoints for S3, Glue, \
+                Athena, CloudWatch Logs and STS, then run the CLI again."
+            }
+            Error::Network { .. } => {
+                "Check your network connection, proxy settings and any AWS_ENDPOINT_URL overrides."
+            }
+            Error::Credentials(_) => {
+                "Configure credentials with `aws configure` or AWS_PROFILE, or run the CLI from AWS CloudShell. \
+                With --role-arn check the trust policy, external ID and MFA code of the role."
+            }
+            Error::Aws { .. } => "See the error code and message above for details.",
+            Error::Prompt(_) => "The CLI must be run in an interactive terminal.",
+            Error::Cancelled => "No changes were made.",
+            Error::JobFailed(_) => {
+                "The instance was kept running so it can be inspected. Check the CloudWatch logs, then \
+                terminate the instance."
+            }
+            Error::Timeout(_) => {
+                "AWS did not apply the change in time, which is usually temporary. Run the CLI again, \
+                leftover IAM resources are removed at the start of the next run."
+            }
+            Error::Config(_) => "Fix or remove the config file, then run the CLI again.",
+            Error::Interrupted(_) => {
+                "EC2 reclaimed the spot capacity and the IAM resources were removed. Run the CLI again \
+                without --spot, or with --on-interruption relaunch to finish on an on-demand instance."
+            }
+            Error::Bundle(_) => {
+                "The wheelhouse is built with pip and tar on this machine. Check that python3 with pip \
+                is installed and PyPI is reachable, then run the command again."
+            }
+            Error::UnsupportedSource(_) => {
+                "Synthetic data can be generated for tables on S3, and for tables in MySQL, PostgreSQL, \
+                Redshift and Oracle databases reached through a Glue JDBC connection."
+            }
+        }
+    }
+
+    /// Error for a response that lacks a field the CLI relies on
+    pub fn unexpected_response(action: &str, missing: &str) -> Self {
+        Error::Aws {
+            action: action.to_string(),
+            code: "UnexpectedResponse".to_string(),
+            message: format!("the response has no {}", missing),
+        }
+    }
+
+    /// Classifies a service error by its code
+    fn from_service(action: &str, code: &str, message: String, is_throttling: bool) -> Self {
+        let action = action.to_string();
+        if is_throttling
+            || ["Throttling", "RequestLimitExceeded", "TooManyRequests"]
+                .iter()
+                .any(|throttling| code.starts_with(throttling))
+        {
+            Error::Throttled { action, message }
+        } else if code.contains("AccessDenied")
+            || ["UnauthorizedOperation", "AuthFailure", "Blocked"].contains(&code)
+        {
+            Error::AccessDenied { action, message }
+        } else if [
+            "UnrecognizedClientException",
+            "InvalidClientTokenId",
+            "ExpiredToken",
+            "ExpiredTokenException",
+            "SignatureDoesNotMatch",
+        ]
+        .contains(&code)
+        {
+            Error::Credentials(format!("Could not {}: {}", action, message))
+        } else if code.contains("LimitExceeded")
+            || code.contains("QuotaExceeded")
+            || code == "InsufficientInstanceCapacity"
+        {
+            Error::QuotaExceeded { action, message }
+        } else if code.contains("NotFound") || code == "NoSuchEntity" {
+            Error::NotFound(format!("Could not {}: {}", action, message))
+        } else {
+            Error::Aws {
+                action,
+                code: code.to_string(),
+                message,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AccessDenied { action, message } => {
+                write!(f, "Access denied, could not {}: {}", action, message)
+            }
+            Error::NotFound(message) => write!(f, "{}", message),
+            Error::Throttled { action, message } => {
+                write!(f, "Throttled, could not {}: {}", action, message)
+            }
+            Error::QuotaExceeded { action, message } => {
+                write!(f, "Quota exceeded, could not {}: {}", action, message)
+            }
+            Error::MisconfiguredVpc(message) => write!(f, "{}", message),
+            Error::Network { action, message } => {
+                write!(f, "Could not reach AWS to {}: {}", action, message)
+            }
+            Error::Credentials(message) => write!(f, "Invalid AWS credentials. {}", message),
+            Error::Aws {
+                action,
+                code,
+                message,
+            } => write!(f, "Could not {} ({}): {}", action, code, message),
+            Error::Prompt(err) => write!(f, "Prompt failed: {}", err),
+            Error::Cancelled => write!(f, "Cancelled"),
+            Error::JobFailed(message)
+            | Error::Interrupted(message)
+            | Error::Bundle(message)
+            | Error::Timeout(message)
+            | Error::Config(message)
+            | Error::UnsupportedSource(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Prompt(err)
+    }
+}
+
+impl From<CredentialsError> for Error {
+    fn from(err: CredentialsError) -> Self {
+        Error::Credentials(error_chain(&err))
+    }
+}
+
+/// Joins the messages of the error and all its sources
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut messages = vec![err.to_string()];
+    let mut source = err.source();
+    while let Some(err) = source {
+        messages.push(err.to_string());
+        source = err.source();
+    }
+    messages.join(": ")
+}
+
+/// Adds the action that failed to the errors of AWS calls
+pub trait ResultExt<T> {
+    fn context(self, action: &str) -> Result<T>;
+}
+
+impl<T, E, R> ResultExt<T> for std::result::Result<T, SdkError<E, R>>
+where
+    E: ProvideErrorKind + std::error::Error + 'static,
+    R: fmt::Debug,
+{
+    fn context(self, action: &str) -> Result<T> {
+        self.map_err(|err| match err {
+            SdkError::ServiceError(service_error) => {
+                let err = service_error.err();
+                Error::from_service(
+                    action,
+                    err.code().unwrap_or("Unknown"),
+                    err.to_string(),
+                    err.retryable_error_kind() == Some(ErrorKind::ThrottlingError),
+                )
+            }
+            // requests are built with the credentials, a failure here is almost always missing credentials
+            SdkError::ConstructionFailure(_) => {
+                Error::Credentials(format!("Could not {}: {}", action, error_chain(&err)))
+            }
+            SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) => Error::Network {
+                action: action.to_string(),
+                message: error_chain(&err),
+            },
+            _ => Error::Aws {
+                action: action.to_string(),
+                code: "Unknown".to_string(),
+                message: error_chain(&err),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exit_code_of(code: &str) -> u8 {
+        Error::from_service("test", code, "message".to_string(), false).exit_code()
+    }
+
+    #[test]
+    fn service_errors_are_classified_by_code() {
+        let cases = [
+            ("ThrottlingException", 5),
+            ("RequestLimitExceeded", 5),
+            ("TooManyRequestsException", 5),
+            ("AccessDeniedException", 3),
+            ("AccessDenied", 3),
+            ("UnauthorizedOperation", 3),
+            ("AuthFailure", 3),
+            ("Blocked", 3),
+            ("ExpiredToken", 9),
+            ("InvalidClientTokenId", 9),
+            ("SignatureDoesNotMatch", 9),
+            ("LimitExceededException", 6),
+            ("VcpuLimitExceeded", 6),
+            ("ServiceQuotaExceededException", 6),
+            ("InsufficientInstanceCapacity", 6),
+            ("EntityNotFoundException", 4),
+            ("InvalidSubnetID.NotFound", 4),
+            ("NoSuchEntity", 4),
+            ("InvalidParameterValue", 10),
+            ("", 10),
+        ];
+        for (code, exit_code) in cases {
+            assert_eq!(exit_code_of(code), exit_code, "{}", code);
+        }
+    }
+
+    #[test]
+    fn throttling_flag_wins_over_the_code() {
+        let error = Error::from_service("test", "SlowDown", "message".to_string(), true);
+        assert!(matches!(error, Error::Throttled { .. }));
+    }
+
+    #[test]
+    fn unclassified_errors_keep_the_code() {
+        match Error::from_service("run the job", "Boom", "message".to_string(), false) {
+            Error::Aws { action, code, .. } => {
+                assert_eq!(action, "run the job");
+                assert_eq!(code, "Boom");
+            }
+            other => panic!("expected an AWS error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            Error::AccessDenied {
+                action: String::new(),
+                message: String::new(),
+            },
+            Error::NotFound(String::new()),
+            Error::Throttled {
+                action: String::new(),
+                message: String::new(),
+            },
+            Error::QuotaExceeded {
+                action: String::new(),
+                message: String::new(),
+            },
+            Error::MisconfiguredVpc(String::new()),
+            Error::Network {
+                action: String::new(),
+                message: String::new(),
+            },
+            Error::Credentials(String::new()),
+            Error::unexpected_response("", ""),
+            Error::Prompt(std::io::Error::from(std::io::ErrorKind::Other)),
+            Error::Cancelled,
+            Error::JobFailed(String::new()),
+            Error::Timeout(String::new()),
+            Error::Config(String::new()),
+            Error::Interrupted(String::new()),
+            Error::Bundle(String::new()),
+            Error::UnsupportedSource(String::new()),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(Error::exit_code).collect();
+        codes.sort_unstable();
+        assert_eq!(codes, (3..=18).collect::<Vec<u8>>());
+    }
+}
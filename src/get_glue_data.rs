@@ -2,23 +2,22 @@
 //! This module contains functions to get data from AWS Glue for the CLI.
 //! The CLI uses the AWS Glue API to get a list of all AWS Glue databases and tables.
 //! The user can then select a database and table to process.
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_ec2::{Client as EC2_Client, Error};
-use aws_sdk_glue::Client;
-use aws_sdk_sts::Client as StsClient;
-use aws_types::region::Region;
+use crate::aws_context::{arn_partition, AwsContext};
+use crate::cache::{self, CacheRecord};
+use crate::error::{Error, Result, ResultExt};
+use aws_sdk_glue::model::{ConnectionPropertyKey, Table};
+use chrono::{Local, TimeZone};
+use colored::*;
+use futures::future::join_all;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
 
-/// Returns ec2 client for the region specified in the environment or default region
-async fn get_ec2_client() -> Result<EC2_Client, Error> {
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let client = EC2_Client::new(&config);
-    Ok(client)
-}
-
-/// Returns a list of all allowed AWS regions
-pub async fn get_all_regions() -> Result<Vec<String>, Error> {
-    let client = get_ec2_client().await.expect("failed to get ec2 client");
+/// Returns the regions to search for Glue databases, all enabled regions unless limited by the user
+pub async fn get_all_regions(ctx: &AwsContext) -> Result<Vec<String>> {
+    if let Some(regions) = ctx.regions() {
+        return Ok(regions.clone());
+    }
+    let client = ctx.ec2(&ctx.default_region());
 
     // Get all regions
     let regions = client
@@ -26,23 +25,144 @@ pub async fn get_all_regions() -> Result<Vec<String>, Error> {
         .all_regions(false)
         .send()
         .await
-        .expect("failed to get regions");
+        .context("list the regions enabled for the account")?;
 
     // Return a list of region names
     Ok(regions
         .regions
-        .unwrap()
+        .unwrap_or_default()
         .into_iter()
-        .map(|region| region.region_name.unwrap())
+        .filter_map(|region| region.region_name)
         .collect())
 }
 
 /// Glue Database struct to hold database name and region
+/// For resource links the name and catalog id are the ones of the target database,
+/// the link name is kept to query the table through Athena in the caller's catalog.
 #[derive(Clone)]
 pub struct GlueDatabase {
     region: String,
     name: String,
     account_id: String,
+    catalog_id: String,
+    link_name: Option<String>,
+}
+
+/// Storage format of the table data on S3.
+/// The Debug form is passed to the python script to pick the right way to read the table.
+#[derive(Clone, Copy, Debug, PartialEq, Display, EnumString)]
+pub enum TableFormat {
+    Hive,
+    Iceberg,
+    Hudi,
+    #[strum(to_string = "Delta Lake")]
+    DeltaLake,
+    /// Table crawled from a database through a Glue JDBC connection
+    #[strum(to_string = "JDBC")]
+    Jdbc,
+}
+
+/// Glue connection used by the worker to reach a table stored in a database
+#[derive(Clone)]
+pub struct GlueConnection {
+    name: String,
+    subnet_id: String,
+    security_group_ids: Vec<String>,
+    secret_id: Option<String>,
+}
+
+impl GlueConnection {
+    /// Reads the network settings and the credentials secret of a Glue connection
+    pub async fn new(
+        ctx: &AwsContext,
+        region: &str,
+        catalog_id: &str,
+        name: String,
+    ) -> Result<Self> {
+        let client = ctx.glue(region);
+        let action = format!("read the Glue connection {}", name);
+        let connection = client
+            .get_connection()
+            .catalog_id(catalog_id)
+            .name(&name)
+            .hide_password(true)
+            .send()
+            .await
+            .context(&action)?
+            .connection
+            .ok_or_else(|| Error::unexpected_response(&action, "connection"))?;
+        // jdbc:<engine>://..., awswrangler reads SQL Server through pyodbc and the Microsoft ODBC
+        // driver, which the worker does not install
+        let engine = connection
+            .connection_properties()
+            .and_then(|properties| properties.get(&ConnectionPropertyKey::JdbcConnectionUrl))
+            .and_then(|url| url.split(':').nth(1))
+            .unwrap_or_default();
+        if engine.eq_ignore_ascii_case("sqlserver") {
+            return Err(Error::UnsupportedSource(format!(
+                "Glue connection {} reaches a SQL Server database, which the worker cannot read",
+                name
+            )));
+        }
+        let (requirements, subnet_id) = connection
+            .physical_connection_requirements()
+            .and_then(|requirements| Some((requirements, requirements.subnet_id()?)))
+            .ok_or_else(|| {
+                Error::MisconfiguredVpc(format!(
+                    "Glue connection {} has no VPC settings, the worker cannot reach the database",
+                    name
+                ))
+            })?;
+        Ok(GlueConnection {
+            subnet_id: subnet_id.to_string(),
+            security_group_ids: requirements
+                .security_group_id_list()
+                .unwrap_or_default()
+                .to_vec(),
+            secret_id: connection
+                .connection_properties()
+                .and_then(|properties| properties.get(&ConnectionPropertyKey::SecretId))
+                .cloned(),
+            name,
+        })
+    }
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn subnet_id(&self) -> &String {
+        &self.subnet_id
+    }
+    pub fn security_group_ids(&self) -> &Vec<String> {
+        &self.security_group_ids
+    }
+    pub fn secret_id(&self) -> Option<&String> {
+        self.secret_id.as_ref()
+    }
+    /// Returns the ARN of the Secrets Manager secret holding the database credentials, if any.
+    /// Secrets referenced by name get a wildcard for the random suffix Secrets Manager appends.
+    pub fn secret_arn(&self, region: &str, account_id: &str) -> Option<String> {
+        self.secret_id.as_ref().map(|secret_id| {
+            if secret_id.starts_with("arn:") {
+                secret_id.to_string()
+            } else {
+                format!(
+                    "arn:{}:secretsmanager:{}:{}:secret:{}-*",
+                    arn_partition(region),
+                    region,
+                    account_id,
+                    secret_id
+                )
+            }
+        })
+    }
+}
+
+/// Column of a Glue table, as shown in the schema preview
+pub struct GlueColumn {
+    pub name: String,
+    pub data_type: String,
+    pub comment: Option<String>,
+    pub is_partition_key: bool,
 }
 
 /// Glue Table struct to hold table name and database
@@ -50,20 +170,67 @@ pub struct GlueDatabase {
 pub struct GlueTable {
     database: GlueDatabase, // We need to keep the database to get the region
     name: String,
-    s3_location: String,
+    s3_location: String, // for JDBC tables this is the <database>.<schema>.<table> path in the database
+    format: TableFormat,
+    partition_locations: Vec<String>,
+    metadata_locations: Vec<String>, // table format metadata that may live outside of the table location
+    connection_name: Option<String>,
+    connection: Option<GlueConnection>,
+    output_location: Option<String>,
+    sample_size: Option<u64>,
+    // catalog metadata shown in the table picker
+    file_format: Option<String>,
+    column_count: usize,
+    partition_keys: Vec<String>,
+    record_count: Option<u64>,
+    size_bytes: Option<u64>,
+    update_time: Option<i64>, // seconds since the epoch
 }
 /// Glue Table convinience struct to hold table name and database
 impl GlueTable {
-    pub async fn new(database: GlueDatabase, name: String) -> Self {
-        let mut glue_table = GlueTable {
+    /// Builds the table from the catalog entry returned by GetTables or GetTable
+    pub fn new(database: GlueDatabase, table: &Table) -> Self {
+        // views and some crawled tables have no location, they are filtered out by the caller
+        let s3_location = table
+            .storage_descriptor()
+            .and_then(|storage_descriptor| storage_descriptor.location())
+            .unwrap_or_default()
+            .to_string();
+        let format = detect_table_format(table);
+        let parse_parameter =
+            |key: &str| get_table_parameter(table, key).and_then(|value| value.parse::<u64>().ok());
+        GlueTable {
             database,
-            name,
-            s3_location: String::new(),
-        };
-
-        glue_table.set_table_location().await;
-
-        glue_table
+            name: table.name().unwrap_or_default().to_string(),
+            metadata_locations: get_metadata_locations(format, &s3_location, table),
+            s3_location,
+            format,
+            partition_locations: vec![],
+            connection_name: get_table_parameter(table, "connectionName")
+                .map(|name| name.to_string()),
+            connection: None,
+            output_location: None,
+            sample_size: None,
+            file_format: detect_file_format(table),
+            column_count: table
+                .storage_descriptor()
+                .and_then(|storage_descriptor| storage_descriptor.columns())
+                .map(|columns| columns.len())
+                .unwrap_or(0),
+            partition_keys: table
+                .partition_keys()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|column| column.name().map(|name| name.to_string()))
+                .collect(),
+            // crawlers keep the statistics of the last crawl in the table parameters
+            record_count: parse_parameter("recordCount"),
+            size_bytes: parse_parameter("sizeKey"),
+            update_time: table
+                .update_time()
+                .or_else(|| table.create_time())
+                .map(|time| time.secs()),
+        }
     }
     pub fn s3_location(&self) -> &String {
         &self.s3_location
@@ -74,38 +241,369 @@ impl GlueTable {
     pub fn name(&self) -> &String {
         &self.name
     }
+    /// Name of the table the synthetic copy is cataloged as, in the same database
+    pub fn output_table_name(&self) -> String {
+        format!("{}_synthetic", self.name)
+    }
+    pub fn format(&self) -> TableFormat {
+        self.format
+    }
+    /// Returns the table with its catalog metadata, as shown in the table picker
     pub fn format_choice(&self) -> String {
-        format!("{}", self.name)
+        let mut details = vec![match &self.file_format {
+            Some(file_format) if self.format == TableFormat::Hive => file_format.to_string(),
+            Some(file_format) => format!("{} on {}", self.format, file_format),
+            None => self.format.to_string(),
+        }];
+        details.push(format!("{} columns", self.column_count));
+        if !self.partition_keys.is_empty() {
+            details.push(format!("partitioned by {}", self.partition_keys.join(", ")));
+        }
+        if let Some(record_count) = self.record_count {
+            details.push(format!("{} rows", format_count(record_count)));
+        }
+        if let Some(size_bytes) = self.size_bytes {
+            details.push(format_size(size_bytes));
+        }
+        if let Some(update_time) = self
+            .update_time
+            .and_then(|secs| Local.timestamp_opt(secs, 0).single())
+        {
+            details.push(format!("updated {}", update_time.format("%Y-%m-%d")));
+        }
+        format!("{} ({})", self.name, details.join(" · "))
+    }
+    pub fn column_count(&self) -> usize {
+        self.column_count
+    }
+    /// Number of rows recorded by the crawler
+    pub fn record_count(&self) -> Option<u64> {
+        self.record_count
+    }
+    /// Size of the data in bytes recorded by the crawler
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.size_bytes
+    }
+    pub fn is_jdbc(&self) -> bool {
+        self.format == TableFormat::Jdbc
+    }
+    pub fn connection(&self) -> Option<&GlueConnection> {
+        self.connection.as_ref()
+    }
+    /// Number of rows the worker reads from the source, `None` reads the whole table
+    pub fn sample_size(&self) -> Option<u64> {
+        self.sample_size
+    }
+    pub fn set_sample_size(&mut self, sample_size: Option<u64>) {
+        self.sample_size = sample_size;
+    }
+    /// Returns the S3 location the synthetic data is written to.
+    /// Defaults to the table location with a "_synthetic" suffix.
+    pub fn output_location(&self) -> String {
+        match &self.output_location {
+            Some(output_location) => output_location.trim_end_matches('/').to_string(),
+            None => format!("{}_synthetic", self.s3_location.trim_end_matches('/')),
+        }
+    }
+    pub fn set_output_location(&mut self, output_location: String) {
+        self.output_location = Some(output_location);
+    }
+    pub fn output_arn(&self) -> String {
+        to_s3_arn(&self.output_location(), self.partition())
+    }
+    pub fn output_bucket_arn(&self) -> String {
+        self.output_arn().split('/').next().unwrap().to_string()
     }
     pub fn s3_arn(&self) -> String {
-        self.s3_location
-            .replace("s3://", "arn:aws:s3:::")
-            .trim_end_matches(|c| c == '/')
-            .to_string()
+        to_s3_arn(&self.s3_location, self.partition())
+    }
+    /// Partition of the region of the table, the first part of its ARNs
+    pub fn partition(&self) -> &'static str {
+        arn_partition(self.database.region())
+    }
+    /// Returns the ARNs of every distinct S3 prefix holding data or metadata for the table.
+    /// The table location always comes first, partition and metadata locations that live under
+    /// an already listed prefix are dropped as they are covered by it.
+    pub fn s3_source_arns(&self) -> Vec<String> {
+        let mut arns: Vec<String> = vec![self.s3_arn()];
+        let mut partition_arns = self
+            .partition_locations
+            .iter()
+            .chain(self.metadata_locations.iter())
+            .filter(|location| is_s3_location(location))
+            .map(|location| to_s3_arn(location, self.partition()))
+            .collect::<Vec<String>>();
+        // shortest prefixes first so that nested prefixes are always seen after their parent
+        partition_arns.sort_by_key(|arn| arn.len());
+        for arn in partition_arns {
+            if !arns
+                .iter()
+                .any(|known| arn == *known || arn.starts_with(&format!("{}/", known)))
+            {
+                arns.push(arn);
+            }
+        }
+        arns
+    }
+    /// Returns the ARNs of every distinct S3 bucket holding data for the table
+    pub fn s3_bucket_arns(&self) -> Vec<String> {
+        let mut buckets: Vec<String> = vec![];
+        for arn in self.s3_source_arns() {
+            let bucket = arn.split('/').next().unwrap().to_string();
+            if !buckets.contains(&bucket) {
+                buckets.push(bucket);
+            }
+        }
+        buckets
     }
 
-    async fn set_table_location(&mut self) {
-        let client = get_glue_client(self.database.region().to_string()).await;
-        let table = client
+    /// Collects the S3 locations of all partitions of the table.
+    /// Partitions are not required to live under the table location, they can point
+    /// to any prefix or even another bucket.
+    pub async fn set_partition_locations(&mut self, ctx: &AwsContext) -> Result<()> {
+        let client = ctx.glue(self.database.region());
+        let mut partition_locations: Vec<String> = vec![];
+        let mut next_token: Option<String> = None;
+        loop {
+            let response = client
+                .get_partitions()
+                .catalog_id(self.database.catalog_id())
+                .database_name(self.database.name())
+                .table_name(self.name())
+                .set_next_token(next_token)
+                .send()
+                .await
+                .context(&format!("list the partitions of table {}", self.name))?;
+
+            for partition in response.partitions().unwrap_or_default() {
+                if let Some(location) = partition
+                    .storage_descriptor()
+                    .and_then(|storage_descriptor| storage_descriptor.location())
+                {
+                    if is_s3_location(location)
+                        && !partition_locations.iter().any(|known| known == location)
+                    {
+                        partition_locations.push(location.to_string());
+                    }
+                }
+            }
+
+            next_token = response.next_token().map(|token| token.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        self.partition_locations = partition_locations;
+        Ok(())
+    }
+
+    /// Reads the column schema of the table from the Glue catalog, partition keys last
+    pub async fn get_schema(&self, ctx: &AwsContext) -> Result<Vec<GlueColumn>> {
+        let action = format!("read the schema of table {}", self.name);
+        let table = ctx
+            .glue(self.database.region())
             .get_table()
+            .catalog_id(self.database.catalog_id())
             .database_name(self.database.name())
-            .name(self.name())
+            .name(&self.name)
             .send()
             .await
-            .unwrap()
+            .context(&action)?
             .table
-            .unwrap();
-        self.s3_location = table.storage_descriptor.unwrap().location.unwrap();
+            .ok_or_else(|| Error::unexpected_response(&action, "table"))?;
+
+        let columns = table
+            .storage_descriptor()
+            .and_then(|storage_descriptor| storage_descriptor.columns())
+            .unwrap_or_default()
+            .iter()
+            .map(|column| (column, false));
+        let partition_keys = table
+            .partition_keys()
+            .unwrap_or_default()
+            .iter()
+            .map(|column| (column, true));
+        Ok(columns
+            .chain(partition_keys)
+            .map(|(column, is_partition_key)| GlueColumn {
+                name: column.name().unwrap_or_default().to_string(),
+                data_type: column.r#type().unwrap_or_default().to_string(),
+                comment: column.comment().map(|comment| comment.to_string()),
+                is_partition_key,
+            })
+            .collect())
+    }
+
+    /// Reads the Glue connection of a JDBC table, does nothing for tables on S3
+    pub async fn set_connection(&mut self, ctx: &AwsContext) -> Result<()> {
+        if let Some(connection_name) = &self.connection_name {
+            self.connection = Some(
+                GlueConnection::new(
+                    ctx,
+                    self.database.region(),
+                    self.database.catalog_id(),
+                    connection_name.to_string(),
+                )
+                .await?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Returns the value of a table parameter, parameter names are matched case insensitively
+fn get_table_parameter<'a>(table: &'a Table, key: &str) -> Option<&'a str> {
+    table
+        .parameters()?
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Detects the format of the data files from the crawler classification or the storage descriptor
+fn detect_file_format(table: &Table) -> Option<String> {
+    if let Some(classification) = get_table_parameter(table, "classification") {
+        return Some(classification.to_lowercase());
+    }
+    let storage_descriptor = table.storage_descriptor()?;
+    let format_classes = format!(
+        "{} {}",
+        storage_descriptor.input_format().unwrap_or_default(),
+        storage_descriptor
+            .serde_info()
+            .and_then(|serde_info| serde_info.serialization_library())
+            .unwrap_or_default()
+    )
+    .to_lowercase();
+    ["parquet", "orc", "avro", "json", "csv"]
+        .iter()
+        .find(|file_format| format_classes.contains(*file_format))
+        .map(|file_format| file_format.to_string())
+        .or_else(|| {
+            format_classes
+                .contains("textinputformat")
+                .then(|| "text".to_string())
+        })
+}
+
+/// Formats a number of rows with a metric suffix, e.g. 1.2M
+fn format_count(count: u64) -> String {
+    let suffixes = ["", "K", "M", "B"];
+    let mut value = count as f64;
+    let mut suffix = 0;
+    // values that round to 1000.0 move to the next suffix
+    while value >= 999.95 && suffix < suffixes.len() - 1 {
+        value /= 1e3;
+        suffix += 1;
+    }
+    if suffix == 0 {
+        count.to_string()
+    } else {
+        format!("{:.1}{}", value, suffixes[suffix])
+    }
+}
+
+/// Formats a size in bytes with a binary suffix, e.g. 3.4 GiB
+fn format_size(size_bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = size_bytes as f64;
+    let mut unit = 0;
+    // sizes that round to 1024.0 move to the next unit
+    while size >= 1023.95 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size_bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Detects the table format from the markers Iceberg, Hudi and Delta Lake leave in the Glue catalog
+fn detect_table_format(table: &Table) -> TableFormat {
+    let table_type = get_table_parameter(table, "table_type").unwrap_or_default();
+    let provider = get_table_parameter(table, "spark.sql.sources.provider").unwrap_or_default();
+    let input_format = table
+        .storage_descriptor()
+        .and_then(|storage_descriptor| storage_descriptor.input_format())
+        .unwrap_or_default()
+        .to_lowercase();
+    let is_hoodie_table = table
+        .parameters()
+        .map(|parameters| parameters.keys().any(|key| key.starts_with("hoodie.")))
+        .unwrap_or(false);
+
+    // crawlers keep the name of the connection used to reach the database on the table
+    if get_table_parameter(table, "connectionName").is_some() {
+        TableFormat::Jdbc
+    } else if table_type.eq_ignore_ascii_case("iceberg") {
+        TableFormat::Iceberg
+    } else if table_type.eq_ignore_ascii_case("delta") || provider.eq_ignore_ascii_case("delta") {
+        TableFormat::DeltaLake
+    } else if input_format.contains("hudi")
+        || provider.eq_ignore_ascii_case("hudi")
+        || is_hoodie_table
+    {
+        TableFormat::Hudi
+    } else {
+        TableFormat::Hive
+    }
+}
+
+/// Returns the prefixes holding the table format metadata (snapshots, commit timeline, transaction log).
+/// Locations outside of S3 are left out, the worker policies only grant access to S3.
+fn get_metadata_locations(format: TableFormat, s3_location: &str, table: &Table) -> Vec<String> {
+    if !is_s3_location(s3_location) {
+        return vec![];
+    }
+    let s3_location = s3_location.trim_end_matches('/');
+    match format {
+        TableFormat::Hive | TableFormat::Jdbc => vec![],
+        TableFormat::Iceberg => {
+            // Iceberg can be configured to keep metadata and data files away from the table location
+            let mut locations = [
+                "write.metadata.path",
+                "write.data.path",
+                "write.object-storage.path",
+            ]
+            .iter()
+            .filter_map(|key| get_table_parameter(table, key))
+            .filter(|location| is_s3_location(location))
+            .map(|location| location.to_string())
+            .collect::<Vec<String>>();
+            if let Some((metadata_prefix, _)) = get_table_parameter(table, "metadata_location")
+                .filter(|location| is_s3_location(location))
+                .and_then(|location| location.rsplit_once('/'))
+            {
+                locations.push(metadata_prefix.to_string());
+            }
+            locations.push(format!("{}/metadata", s3_location));
+            locations
+        }
+        TableFormat::Hudi => vec![format!("{}/.hoodie", s3_location)],
+        TableFormat::DeltaLake => vec![
+            format!("{}/_delta_log", s3_location),
+            format!("{}/_symlink_format_manifest", s3_location),
+        ],
     }
 }
 
 /// Glue Database convinience struct to hold database name and region
 impl GlueDatabase {
-    pub fn new(region: String, account_id: String, name: String) -> Self {
+    pub fn new(
+        region: String,
+        account_id: String,
+        catalog_id: String,
+        name: String,
+        link_name: Option<String>,
+    ) -> Self {
         GlueDatabase {
             region,
             account_id,
+            catalog_id,
             name,
+            link_name,
         }
     }
     pub fn region(&self) -> &String {
@@ -117,101 +615,548 @@ impl GlueDatabase {
     pub fn name(&self) -> &String {
         &self.name
     }
+    /// Account id of the catalog owning the database
+    pub fn catalog_id(&self) -> &String {
+        &self.catalog_id
+    }
+    pub fn is_shared(&self) -> bool {
+        self.catalog_id != self.account_id
+    }
+    pub fn link_name(&self) -> Option<&String> {
+        self.link_name.as_ref()
+    }
+    /// Name of the database in the caller's catalog, which is what Athena sees
+    pub fn query_name(&self) -> &String {
+        self.link_name.as_ref().unwrap_or(&self.name)
+    }
     pub fn format_choice(&self) -> String {
-        format!("{} in {}", self.name, self.region)
+        if self.is_shared() {
+            format!(
+                "{} in {} (shared from {})",
+                self.query_name(),
+                self.region,
+                self.catalog_id
+            )
+        } else {
+            format!("{} in {}", self.name, self.region)
+        }
+    }
+}
+
+impl CacheRecord for GlueDatabase {
+    fn to_fields(&self) -> Vec<String> {
+        vec![
+            self.region.clone(),
+            self.account_id.clone(),
+            self.catalog_id.clone(),
+            self.name.clone(),
+            self.link_name.clone().unwrap_or_default(),
+        ]
+    }
+    fn from_fields(fields: &[String]) -> Option<Self> {
+        match fields {
+            [region, account_id, catalog_id, name, link_name] => Some(GlueDatabase::new(
+                region.to_string(),
+                account_id.to_string(),
+                catalog_id.to_string(),
+                name.to_string(),
+                Some(link_name.to_string()).filter(|link_name| !link_name.is_empty()),
+            )),
+            _ => None,
+        }
     }
 }
 
-/// Get Glue client for a region
-async fn get_glue_client(region: String) -> Client {
-    let config = aws_config::from_env()
-        .region(Region::new(region))
-        .load()
-        .await;
+/// Tables are cached as listed, before partitions and connections are read for the selected table
+impl CacheRecord for GlueTable {
+    fn to_fields(&self) -> Vec<String> {
+        let mut fields = self.database.to_fields();
+        fields.extend([
+            self.name.clone(),
+            self.s3_location.clone(),
+            self.format.to_string(),
+            self.connection_name.clone().unwrap_or_default(),
+            self.file_format.clone().unwrap_or_default(),
+            self.column_count.to_string(),
+            self.partition_keys.join(","),
+            to_field(self.record_count),
+            to_field(self.size_bytes),
+            to_field(self.update_time),
+        ]);
+        fields.extend(self.metadata_locations.iter().cloned());
+        fields
+    }
+    fn from_fields(fields: &[String]) -> Option<Self> {
+        let (database_fields, fields) = fields.split_at(fields.len().min(5));
+        let [name, s3_location, format, connection_name, file_format, column_count, partition_keys, record_count, size_bytes, update_time, metadata_locations @ ..] =
+            fields
+        else {
+            return None;
+        };
+        Some(GlueTable {
+            database: GlueDatabase::from_fields(database_fields)?,
+            name: name.to_string(),
+            s3_location: s3_location.to_string(),
+            format: TableFormat::from_str(format).ok()?,
+            partition_locations: vec![],
+            metadata_locations: metadata_locations.to_vec(),
+            connection_name: Some(connection_name.to_string()).filter(|name| !name.is_empty()),
+            connection: None,
+            output_location: None,
+            sample_size: None,
+            file_format: Some(file_format.to_string()).filter(|format| !format.is_empty()),
+            column_count: column_count.parse().ok()?,
+            partition_keys: partition_keys
+                .split(',')
+                .filter(|key| !key.is_empty())
+                .map(|key| key.to_string())
+                .collect(),
+            record_count: record_count.parse().ok(),
+            size_bytes: size_bytes.parse().ok(),
+            update_time: update_time.parse().ok(),
+        })
+    }
+}
 
-    Client::new(&config)
+/// Formats an optional number as a cache field, empty when missing
+fn to_field<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
 }
 
-async fn get_account_id(region: String) -> String {
-    let config = aws_config::from_env()
-        .region(Region::new(region))
-        .load()
-        .await;
+/// Returns the location without its s3:// scheme, which S3 accepts in any case
+fn strip_s3_scheme(location: &str) -> Option<&str> {
+    let scheme = location.get(.."s3://".len())?;
+    scheme
+        .eq_ignore_ascii_case("s3://")
+        .then(|| &location[scheme.len()..])
+}
 
-    let client = StsClient::new(&config);
+/// Checks that the location is an s3:// URI, the only locations `to_s3_arn` converts
+fn is_s3_location(location: &str) -> bool {
+    strip_s3_scheme(location).is_some()
+}
 
-    client
-        .get_caller_identity()
-        .send()
-        .await
-        .unwrap()
-        .account
-        .unwrap()
+/// Splits an s3:// location into its bucket and key
+pub fn split_s3_location(s3_location: &str) -> Option<(&str, &str)> {
+    strip_s3_scheme(s3_location)?
+        .split_once('/')
+        .filter(|(bucket, _)| !bucket.is_empty())
+}
+
+/// Converts an s3:// location into an S3 ARN of the partition without the trailing slash
+pub fn to_s3_arn(s3_location: &str, partition: &str) -> String {
+    format!(
+        "arn:{}:s3:::{}",
+        partition,
+        strip_s3_scheme(s3_location)
+            .unwrap_or(s3_location)
+            .trim_end_matches('/')
+    )
+}
+
+/// Get all databases in all regions, cached per account and searched regions
+pub async fn get_aws_glue_databases(ctx: &AwsContext) -> Result<Vec<GlueDatabase>> {
+    let cache_key = match ctx.regions() {
+        Some(regions) => format!("databases-{}", regions.join(",")),
+        None => "databases-all".to_string(),
+    };
+    cache::get_or_load(ctx, &cache_key, || load_glue_databases(ctx)).await
 }
-/// Get all databases in all regions
-pub async fn get_aws_glue_databases() -> Vec<GlueDatabase> {
+
+/// Get all databases in all regions, regions are queried concurrently
+async fn load_glue_databases(ctx: &AwsContext) -> Result<Vec<GlueDatabase>> {
     // Get all regions
-    let my_regions = get_all_regions().await.unwrap();
-    // get current account id from sts get_caller_identity
-    let accound_id = get_account_id(my_regions[0].to_string()).await;
+    let my_regions = get_all_regions(ctx).await?;
+    // current account id from sts get_caller_identity, in the region of the environment
+    // as the searched regions may include opt-in regions STS cannot be reached in
+    let accound_id = ctx.account_id().await?;
+
+    // Get all databases in all regions, keeping the order of the regions
+    let results = join_all(
+        my_regions
+            .iter()
+            .map(|my_region| get_regional_glue_databases(ctx, my_region, &accound_id)),
+    )
+    .await;
     let mut databases: Vec<GlueDatabase> = vec![];
+    let mut first_error = None;
+    for (my_region, result) in my_regions.iter().zip(results) {
+        match result {
+            Ok(regional_databases) => databases.extend(regional_databases),
+            // an SCP or an opt-in region may deny Glue in one region, the others are still searched
+            Err(err) => {
+                eprintln!(
+                    "{}",
+                    format!("Skipping region {}: {}", my_region, err).yellow()
+                );
+                first_error.get_or_insert(err);
+            }
+        }
+    }
 
-    // Get all databases in all regions
-    for my_region in &my_regions {
-        // Get glue client for the region
-        let client = get_glue_client(my_region.to_string()).await;
+    if databases.is_empty() {
+        return Err(first_error.unwrap_or_else(|| {
+            Error::NotFound("No Glue Databases found in any region".to_string())
+        }));
+    }
+    Ok(databases)
+}
 
-        // Get all databases in the region
-        let mut regional_databases: Vec<GlueDatabase> = client
+/// Get all databases in one region
+async fn get_regional_glue_databases(
+    ctx: &AwsContext,
+    my_region: &str,
+    accound_id: &str,
+) -> Result<Vec<GlueDatabase>> {
+    // Get glue client for the region
+    let client = ctx.glue(my_region);
+    let mut databases: Vec<GlueDatabase> = vec![];
+    let mut next_token: Option<String> = None;
+    loop {
+        let response = client
             .get_databases()
+            .set_next_token(next_token)
             .send()
             .await
-            .expect("failed to get databases")
-            .database_list()
-            .unwrap()
-            .iter()
-            .map(|database| {
-                GlueDatabase::new(
-                    my_region.to_string(),
-                    accound_id.to_string(),
-                    database.name().unwrap().to_string(),
-                )
-            })
-            .collect();
-        databases.append(&mut regional_databases);
-    }
-    // if no throw error and exit
-    if databases.is_empty() {
-        println!("No Glue Databases found");
-        std::process::exit(1);
+            .context(&format!("list the Glue databases in {}", my_region))?;
+
+        databases.extend(
+            response
+                .database_list()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|database| match database.target_database() {
+                    // resource links point to a database in a (possibly) different catalog
+                    Some(target) => Some(GlueDatabase::new(
+                        my_region.to_string(),
+                        accound_id.to_string(),
+                        target.catalog_id().unwrap_or(accound_id).to_string(),
+                        target.database_name()?.to_string(),
+                        Some(database.name()?.to_string()),
+                    )),
+                    None => Some(GlueDatabase::new(
+                        my_region.to_string(),
+                        accound_id.to_string(),
+                        database.catalog_id().unwrap_or(accound_id).to_string(),
+                        database.name()?.to_string(),
+                        None,
+                    )),
+                }),
+        );
+
+        next_token = response.next_token().map(|token| token.to_string());
+        if next_token.is_none() {
+            break;
+        }
     }
-    databases
+    Ok(databases)
 }
 
-/// Get all tables in a database
-pub async fn get_one_glue_table(database: &GlueDatabase) -> Vec<GlueTable> {
-    // Get glue client for the region
-    let client = get_glue_client(database.region().to_string()).await;
+/// Get all tables in a database, cached per account
+pub async fn get_one_glue_table(
+    ctx: &AwsContext,
+    database: &GlueDatabase,
+) -> Result<Vec<GlueTable>> {
+    let cache_key = format!(
+        "tables-{}-{}-{}",
+        database.region(),
+        database.catalog_id(),
+        database.name()
+    );
+    cache::get_or_load(ctx, &cache_key, || load_glue_tables(ctx, database)).await
+}
 
-    let response = client
-        .get_tables()
-        .database_name(database.name())
-        .send()
-        .await
-        .expect("failed to get tables");
+/// Get all tables in a database from the Glue catalog
+async fn load_glue_tables(ctx: &AwsContext, database: &GlueDatabase) -> Result<Vec<GlueTable>> {
+    // Get glue client for the region
+    let client = ctx.glue(database.region());
 
     let mut tables: Vec<GlueTable> = vec![];
-    for table in response.table_list().unwrap().iter() {
-        let glue_table = GlueTable::new(database.clone(), table.name().unwrap().to_string()).await;
-        // only keep s3 based tables
-        if glue_table.s3_location().to_lowercase().starts_with("s3://") {
-            tables.push(glue_table);
+    let mut next_token: Option<String> = None;
+    loop {
+        let response = client
+            .get_tables()
+            .catalog_id(database.catalog_id())
+            .database_name(database.name())
+            .set_next_token(next_token)
+            .send()
+            .await
+            .context(&format!("list the tables of database {}", database.name()))?;
+
+        // GetTables returns the full table definitions, no need to read the tables one by one
+        for table in response.table_list().unwrap_or_default() {
+            let glue_table = GlueTable::new(database.clone(), table);
+            // only keep s3 based tables and tables reachable through a glue connection
+            if is_s3_location(glue_table.s3_location()) || glue_table.is_jdbc() {
+                tables.push(glue_table);
+            }
+        }
+
+        next_token = response.next_token().map(|token| token.to_string());
+        if next_token.is_none() {
+            break;
         }
     }
-    // if no tables throw error and exit
     if tables.is_empty() {
-        println!("No Glue Tables on S3 found in database {}", database.name());
-        std::process::exit(1);
+        return Err(Error::NotFound(format!(
+            "No Glue Tables on S3 or JDBC connections found in database {}",
+            database.name()
+        )));
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_glue::model::{SerDeInfo, StorageDescriptor};
+
+    fn table(location: &str, parameters: &[(&str, &str)]) -> Table {
+        let mut builder = Table::builder().name("orders").storage_descriptor(
+            StorageDescriptor::builder()
+                .location(location)
+                .input_format("org.apache.hadoop.mapred.TextInputFormat")
+                .build(),
+        );
+        for (key, value) in parameters {
+            builder = builder.parameters(*key, *value);
+        }
+        builder.build()
+    }
+
+    fn glue_table(table: &Table) -> GlueTable {
+        let database = GlueDatabase::new(
+            "eu-west-1".to_string(),
+            "123456789012".to_string(),
+            "123456789012".to_string(),
+            "sales".to_string(),
+            None,
+        );
+        GlueTable::new(database, table)
+    }
+
+    #[test]
+    fn only_s3_uris_are_s3_locations() {
+        assert!(is_s3_location("s3://bucket/prefix"));
+        assert!(is_s3_location("S3://bucket/prefix"));
+        assert!(!is_s3_location("s3a://bucket/prefix"));
+        assert!(!is_s3_location("hdfs://namenode/warehouse/orders"));
+        assert!(!is_s3_location("sales.public.orders"));
+        assert!(!is_s3_location(""));
+    }
+
+    #[test]
+    fn split_s3_location_returns_bucket_and_key() {
+        assert_eq!(
+            split_s3_location("s3://bucket/a/b.csv"),
+            Some(("bucket", "a/b.csv"))
+        );
+        assert_eq!(
+            split_s3_location("S3://bucket/a/b.csv"),
+            Some(("bucket", "a/b.csv"))
+        );
+        assert_eq!(split_s3_location("s3://bucket/"), Some(("bucket", "")));
+        assert_eq!(split_s3_location("s3://bucket"), None);
+        assert_eq!(split_s3_location("s3:///key"), None);
+        assert_eq!(split_s3_location("file:///tmp/a.csv"), None);
+    }
+
+    #[test]
+    fn to_s3_arn_drops_the_trailing_slash() {
+        assert_eq!(
+            to_s3_arn("s3://bucket/warehouse/orders/", "aws"),
+            "arn:aws:s3:::bucket/warehouse/orders"
+        );
+        assert_eq!(
+            to_s3_arn("S3://bucket/Warehouse/Orders/", "aws"),
+            "arn:aws:s3:::bucket/Warehouse/Orders"
+        );
+        assert_eq!(
+            to_s3_arn("s3://bucket/orders", "aws-cn"),
+            "arn:aws-cn:s3:::bucket/orders"
+        );
+    }
+
+    #[test]
+    fn format_count_uses_metric_suffixes() {
+        let cases = [
+            (0, "0"),
+            (999, "999"),
+            (1_000, "1.0K"),
+            (1_250, "1.2K"),
+            (999_949, "999.9K"),
+            (999_999, "1.0M"),
+            (12_345_678, "12.3M"),
+            (999_999_999, "1.0B"),
+            (4_200_000_000_000, "4200.0B"),
+        ];
+        for (count, formatted) in cases {
+            assert_eq!(format_count(count), formatted, "{}", count);
+        }
+    }
+
+    #[test]
+    fn format_size_uses_binary_units() {
+        let cases = [
+            (0, "0 B"),
+            (1_023, "1023 B"),
+            (1_024, "1.0 KiB"),
+            (1_536, "1.5 KiB"),
+            (1_048_575, "1.0 MiB"),
+            (5 * 1024 * 1024 * 1024, "5.0 GiB"),
+            (u64::MAX, "16384.0 PiB"),
+        ];
+        for (size_bytes, formatted) in cases {
+            assert_eq!(format_size(size_bytes), formatted, "{}", size_bytes);
+        }
+    }
+
+    #[test]
+    fn detect_file_format_reads_the_classification_then_the_classes() {
+        let with_classes = |input_format: &str, serialization_library: &str| {
+            Table::builder()
+                .storage_descriptor(
+                    StorageDescriptor::builder()
+                        .input_format(input_format)
+                        .serde_info(
+                            SerDeInfo::builder()
+                                .serialization_library(serialization_library)
+                                .build(),
+                        )
+                        .build(),
+                )
+                .build()
+        };
+        let text_input = "org.apache.hadoop.mapred.TextInputFormat";
+        let cases = [
+            (
+                table("s3://bucket/orders", &[("classification", "Parquet")]),
+                Some("parquet"),
+            ),
+            (
+                with_classes(
+                    "org.apache.hadoop.hive.ql.io.parquet.MapredParquetInputFormat",
+                    "org.apache.hadoop.hive.ql.io.parquet.serde.ParquetHiveSerDe",
+                ),
+                Some("parquet"),
+            ),
+            (
+                with_classes("org.apache.hadoop.hive.ql.io.orc.OrcInputFormat", ""),
+                Some("orc"),
+            ),
+            (
+                with_classes(text_input, "org.apache.hadoop.hive.serde2.OpenCSVSerde"),
+                Some("csv"),
+            ),
+            (
+                with_classes(text_input, "org.openx.data.jsonserde.JsonSerDe"),
+                Some("json"),
+            ),
+            (
+                with_classes(
+                    text_input,
+                    "org.apache.hadoop.hive.serde2.lazy.LazySimpleSerDe",
+                ),
+                Some("text"),
+            ),
+            (with_classes("", ""), None),
+            (Table::builder().name("view").build(), None),
+        ];
+        for (table, file_format) in cases {
+            assert_eq!(detect_file_format(&table).as_deref(), file_format);
+        }
+    }
+
+    #[test]
+    fn detect_table_format_reads_the_catalog_markers() {
+        let cases: [(&[(&str, &str)], TableFormat); 6] = [
+            (&[], TableFormat::Hive),
+            (&[("table_type", "ICEBERG")], TableFormat::Iceberg),
+            (
+                &[("spark.sql.sources.provider", "delta")],
+                TableFormat::DeltaLake,
+            ),
+            (&[("hoodie.table.name", "orders")], TableFormat::Hudi),
+            (&[("connectionName", "postgres")], TableFormat::Jdbc),
+            (
+                &[("connectionName", "postgres"), ("table_type", "iceberg")],
+                TableFormat::Jdbc,
+            ),
+        ];
+        for (parameters, format) in cases {
+            assert_eq!(
+                detect_table_format(&table("s3://bucket/orders", parameters)),
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn s3_source_arns_drop_nested_prefixes() {
+        let mut table = glue_table(&table("s3://bucket/orders/", &[]));
+        table.partition_locations = vec![
+            "s3://bucket/orders/day=1".to_string(),
+            "s3://other/orders/day=2/".to_string(),
+        ];
+        assert_eq!(
+            table.s3_source_arns(),
+            vec![
+                "arn:aws:s3:::bucket/orders".to_string(),
+                "arn:aws:s3:::other/orders/day=2".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn s3_source_arns_skip_non_s3_locations() {
+        let mut table = glue_table(&table(
+            "s3://bucket/orders",
+            &[
+                ("table_type", "iceberg"),
+                ("write.data.path", "hdfs://namenode/orders/data"),
+                ("write.metadata.path", "s3://metadata/orders"),
+                (
+                    "metadata_location",
+                    "file:///tmp/orders/00001.metadata.json",
+                ),
+            ],
+        ));
+        table.partition_locations = vec!["hdfs://namenode/orders/day=1".to_string()];
+        assert_eq!(
+            table.s3_source_arns(),
+            vec![
+                "arn:aws:s3:::bucket/orders".to_string(),
+                "arn:aws:s3:::metadata/orders".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn s3_source_arns_accept_upper_case_schemes() {
+        let mut table = glue_table(&table(
+            "S3://bucket/orders/",
+            &[
+                ("table_type", "iceberg"),
+                ("write.metadata.path", "S3://metadata/orders"),
+            ],
+        ));
+        table.partition_locations = vec!["S3://other/orders/day=1".to_string()];
+        assert_eq!(
+            table.s3_source_arns(),
+            vec![
+                "arn:aws:s3:::bucket/orders".to_string(),
+                "arn:aws:s3:::metadata/orders".to_string(),
+                "arn:aws:s3:::other/orders/day=1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_locations_of_a_table_outside_s3_are_empty() {
+        let table = glue_table(&table(
+            "hdfs://namenode/orders",
+            &[("table_type", "iceberg")],
+        ));
+        assert!(table.metadata_locations.is_empty());
     }
-    tables
 }
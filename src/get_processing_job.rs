@@ -1,5 +1,9 @@
 //! # get_processing_job
+use crate::aws_context::AwsContext;
+use crate::cache::{self, CacheRecord};
+use crate::cli::{Architecture, OnInterruption};
 use crate::cw_logging::CWLogSender;
+use crate::error::{Error, Result, ResultExt};
 /// This module contains the code to create an EC2 instance and run the workload on it
 /// The EC2 instance is created in the same region as the source data and in the private subnet
 /// with available IP addresses.
@@ -8,197 +12,535 @@ use crate::cw_logging::CWLogSender;
 /// shell script created that wraps SytheticTabularDataGenerator python script and runs as part of user data of the EC2 instance
 use crate::get_glue_data::GlueTable;
 use crate::manage_iam::{cleanup_aim, get_iam_instance_profile_specification};
+use crate::manage_security_group::{create_job_security_group, delete_job_security_group};
 use crate::progress_tracker::{JobState, ProgressTracker};
+use crate::retry::retry_while;
+use crate::sizing::SynthesisModel;
 use crate::PROJECT_NAME;
 use aws_sdk_ec2::model::Filter;
 use aws_sdk_ec2::model::{
-    BlockDeviceMapping, EbsBlockDevice, InstanceStateName, InstanceType, ResourceType, Tag,
-    TagSpecification,
+    BlockDeviceMapping, EbsBlockDevice, HttpTokensState, InstanceInterruptionBehavior,
+    InstanceMarketOptionsRequest, InstanceMetadataEndpointState, InstanceMetadataOptionsRequest,
+    InstanceStateName, InstanceType, LocationType, MarketType, ResourceType, RouteTable,
+    SpotInstanceType, SpotMarketOptions, Tag, TagSpecification, VolumeType, VpcEndpoint,
+    VpcEndpointType,
 };
-use aws_sdk_ec2::{Client, Error};
 
-use aws_types::region::Region;
 use base64::{engine::general_purpose, Engine as _};
 use colored::*;
+use std::str::FromStr;
+use std::time::Duration;
+use strum_macros::{Display, EnumString};
+
+/// Prefix of the state reason codes of spot instances reclaimed by EC2
+const SPOT_INTERRUPTION_REASON: &str = "Server.SpotInstance";
+/// Pinned Python packages of the worker, also bundled by `synth_table bundle`
+pub const REQUIREMENTS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/scripts/requirements.txt"
+));
+/// Public SSM parameter of the latest Amazon Linux 2023 AMI, in the form EC2 resolves at launch,
+/// followed by the architecture
+const AL2023_AMI_PARAMETER: &str =
+    "resolve:ssm:/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-";
+/// Root device of the Amazon Linux AMIs
+const AMAZON_LINUX_ROOT_DEVICE: &str = "/dev/xvda";
+/// Services the worker calls, reached through VPC endpoints in subnets without a NAT gateway
+const ENDPOINT_SERVICES: [&str; 5] = ["s3", "glue", "athena", "logs", "sts"];
+/// Time EC2 gets to see the instance profile created for the job
+const INSTANCE_PROFILE_PROPAGATION_TIMEOUT: Duration = Duration::from_secs(120);
+/// Time between two reads of the state of an instance that is still starting
+const PENDING_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Instance id reported when the worker instance is simulated
+const SIMULATED_INSTANCE_ID: &str = "i-simulated";
+/// Task sending the log lines of the simulated worker instance
+type SimulatedWorker = tokio::task::JoinHandle<Result<()>>;
+/// Log lines sent by the simulated worker, in the order of the bootstrap script
+const SIMULATED_WORKER_LOGS: [&str; 5] = [
+    "Python 3.11 Installed ...",
+    "Required packages installed ...",
+    "Starting Data Creation Script ...",
+    "Simulated instance, no synthetic data is generated ...",
+    "Done",
+];
+
+/// Settings of the job chosen in the wizard
+#[derive(Clone)]
+pub struct JobOptions {
+    pub architecture: Architecture,
+    pub instance_type: InstanceType,
+    pub model: SynthesisModel,
+    /// Launch the instance on spot capacity
+    pub spot: bool,
+    pub on_interruption: OnInterruption,
+    /// Size in GiB of the root volume
+    pub volume_size: i32,
+    pub volume_type: VolumeType,
+    /// Encrypt the root volume, with the default EBS key unless a KMS key is given
+    pub encrypted: bool,
+    pub kms_key_id: Option<String>,
+    /// AMI id or `resolve:ssm:` parameter of the image, Amazon Linux 2023 when `None`
+    pub ami: Option<String>,
+    /// Python packages of the worker, installed from PyPI when `None`
+    pub wheelhouse: Option<Wheelhouse>,
+}
+
+/// Archive of the Python wheels of the worker on S3, see `synth_table bundle`
+#[derive(Clone)]
+pub struct Wheelhouse {
+    pub location: String,
+    /// SHA-256 of the archive, checked by the worker before installing from it
+    pub sha256: Option<String>,
+}
+
+/// How a subnet reaches the AWS APIs the worker calls
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+pub enum SubnetEgress {
+    #[strum(serialize = "NAT gateway")]
+    NatGateway,
+    /// No internet access, the Python packages come from a wheelhouse on S3
+    #[strum(serialize = "VPC endpoints")]
+    VpcEndpoints,
+}
+
+impl JobOptions {
+    fn wheelhouse_location(&self) -> Option<&str> {
+        self.wheelhouse
+            .as_ref()
+            .map(|wheelhouse| wheelhouse.location.as_str())
+    }
+}
+
 #[derive(Clone)]
 pub struct ValidSubnet {
     vpc: String,
+    /// Name tag of the VPC, empty without one
+    vpc_name: String,
     subnet: String,
+    /// Name tag of the subnet, empty without one
+    name: String,
+    availability_zone: String,
+    available_ips: i32,
+    egress: SubnetEgress,
 }
 impl ValidSubnet {
-    fn new(vpc: String, subnet: String) -> Self {
-        Self { vpc, subnet }
-    }
-    pub fn get_vpc(&self) -> &String {
-        &self.vpc
-    }
     pub fn get_subnet(&self) -> &String {
         &self.subnet
     }
+    pub fn get_availability_zone(&self) -> &String {
+        &self.availability_zone
+    }
+    pub fn get_egress(&self) -> SubnetEgress {
+        self.egress
+    }
     pub fn format_for_display(&self) -> String {
-        format!("Subnet: {} in VPC: {}", self.get_subnet(), self.get_vpc())
+        let with_name = |name: &str, id: &str| match name {
+            "" => id.to_string(),
+            name => format!("{} ({})", name, id),
+        };
+        format!(
+            "{}  {}  {} free IPs  VPC {}  through {}",
+            with_name(&self.name, &self.subnet),
+            self.availability_zone,
+            self.available_ips,
+            with_name(&self.vpc_name, &self.vpc),
+            self.egress
+        )
+    }
+}
+impl CacheRecord for ValidSubnet {
+    fn to_fields(&self) -> Vec<String> {
+        vec![
+            self.vpc.clone(),
+            self.vpc_name.clone(),
+            self.subnet.clone(),
+            self.name.clone(),
+            self.availability_zone.clone(),
+            self.available_ips.to_string(),
+            self.egress.to_string(),
+        ]
+    }
+    fn from_fields(fields: &[String]) -> Option<Self> {
+        match fields {
+            [vpc, vpc_name, subnet, name, availability_zone, available_ips, egress] => {
+                Some(ValidSubnet {
+                    vpc: vpc.to_string(),
+                    vpc_name: vpc_name.to_string(),
+                    subnet: subnet.to_string(),
+                    name: name.to_string(),
+                    availability_zone: availability_zone.to_string(),
+                    available_ips: available_ips.parse().ok()?,
+                    egress: SubnetEgress::from_str(egress).ok()?,
+                })
+            }
+            _ => None,
+        }
     }
 }
-/// Returns ec2 client for the region specified in the environment or default region
-async fn get_ec2_client(region: &str) -> Client {
-    let config = aws_config::from_env()
-        .region(Region::new(region.to_string()))
-        .load()
-        .await;
-    Client::new(&config)
+
+/// Value of the Name tag, empty without one
+fn name_tag(tags: Option<&[Tag]>) -> String {
+    tags.unwrap_or_default()
+        .iter()
+        .find(|tag| tag.key() == Some("Name"))
+        .and_then(|tag| tag.value())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Availability zones of the region that offer the instance type
+pub async fn get_instance_type_zones(
+    ctx: &AwsContext,
+    my_region: &str,
+    instance_type: &InstanceType,
+) -> Result<Vec<String>> {
+    let action = format!(
+        "list the availability zones offering {}",
+        instance_type.as_str()
+    );
+    let output = ctx
+        .ec2(my_region)
+        .describe_instance_type_offerings()
+        .location_type(LocationType::AvailabilityZone)
+        .filters(
+            Filter::builder()
+                .name("instance-type")
+                .values(instance_type.as_str())
+                .build(),
+        )
+        .send()
+        .await
+        .context(&action)?;
+    Ok(output
+        .instance_type_offerings()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|offering| offering.location().map(|zone| zone.to_string()))
+        .collect())
+}
+
+/// Availability zone of the subnet, for the subnets of Glue connections that are not listed
+pub async fn get_subnet_zone(ctx: &AwsContext, my_region: &str, subnet_id: &str) -> Result<String> {
+    let action = format!("describe the subnet {}", subnet_id);
+    ctx.ec2(my_region)
+        .describe_subnets()
+        .subnet_ids(subnet_id)
+        .send()
+        .await
+        .context(&action)?
+        .subnets()
+        .and_then(|subnets| subnets.first())
+        .and_then(|subnet| subnet.availability_zone())
+        .map(|zone| zone.to_string())
+        .ok_or_else(|| Error::unexpected_response(&action, "availability zone"))
+}
+
+/// get vpc list and pick a suitable subnet, cached per account and region
+pub async fn get_subnet_list(ctx: &AwsContext, my_region: &str) -> Result<Vec<ValidSubnet>> {
+    let cache_key = format!("subnets-{}", my_region);
+    cache::get_or_load(ctx, &cache_key, || load_subnet_list(ctx, my_region)).await
+}
+
+/// Checks if the subnet using the route table reaches the service through a VPC endpoint.
+/// Gateway endpoints must be associated with the route table, interface endpoints need
+/// private DNS so the default service endpoints resolve to them.
+fn has_vpc_endpoint(
+    endpoints: &[VpcEndpoint],
+    my_region: &str,
+    service: &str,
+    route_table_id: &str,
+) -> bool {
+    let service_name = format!("com.amazonaws.{}.{}", my_region, service);
+    endpoints.iter().any(|endpoint| {
+        endpoint.service_name() == Some(service_name.as_str())
+            && match endpoint.vpc_endpoint_type() {
+                Some(VpcEndpointType::Gateway) => endpoint
+                    .route_table_ids()
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|id| id == route_table_id),
+                Some(VpcEndpointType::Interface) => endpoint.private_dns_enabled().unwrap_or(false),
+                _ => false,
+            }
+    })
 }
-/// get vpc list and pick a suitable subnet
+
+/// Returns the route table of the subnet.
+/// Subnets without a route table of their own use the main route table of the vpc.
+fn subnet_route_table<'a>(
+    route_tables: &'a [RouteTable],
+    subnet_id: &str,
+) -> Option<&'a RouteTable> {
+    let is_associated = |route_table: &&RouteTable, main: bool| {
+        route_table
+            .associations()
+            .unwrap_or_default()
+            .iter()
+            .any(|association| match main {
+                true => association.main().unwrap_or(false),
+                false => association.subnet_id() == Some(subnet_id),
+            })
+    };
+    route_tables
+        .iter()
+        .find(|route_table| is_associated(route_table, false))
+        .or_else(|| {
+            route_tables
+                .iter()
+                .find(|route_table| is_associated(route_table, true))
+        })
+}
+
+/// Returns how a subnet using the route table reaches AWS, `None` when it cannot
+fn subnet_egress(
+    route_table: &RouteTable,
+    nat_gateways: &[String],
+    endpoints: &[VpcEndpoint],
+    my_region: &str,
+) -> Option<SubnetEgress> {
+    let has_nat_route = route_table
+        .routes()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|route| route.nat_gateway_id())
+        .any(|nat_gateway_id| nat_gateways.iter().any(|id| id == nat_gateway_id));
+    let route_table_id = route_table.route_table_id().unwrap_or_default();
+    if has_nat_route {
+        Some(SubnetEgress::NatGateway)
+    } else if ENDPOINT_SERVICES
+        .iter()
+        .all(|service| has_vpc_endpoint(endpoints, my_region, service, route_table_id))
+    {
+        Some(SubnetEgress::VpcEndpoints)
+    } else {
+        None
+    }
+}
+
 /// Suitable subnet is a private subnet with
 /// 1) available IP addresses
-/// 2)  NAT gateway in a vpc
-/// 3) route to the NAT gateway from the subnet
-pub async fn get_subnet_list(my_region: &str) -> Result<Vec<ValidSubnet>, Error> {
-    let client = get_ec2_client(my_region).await;
+/// 2) a route to a NAT gateway of its vpc, or
+///    VPC endpoints for every service the worker calls
+async fn load_subnet_list(ctx: &AwsContext, my_region: &str) -> Result<Vec<ValidSubnet>> {
+    let client = ctx.ec2(my_region);
 
     let vpc_list = &client
         .describe_vpcs()
         .send()
         .await
-        .expect("failed to get vpc list")
+        .context("list the VPCs")?
         .vpcs()
-        .unwrap()
+        .unwrap_or_default()
         .iter()
-        .map(|vpc| vpc.vpc_id().unwrap().to_string())
-        .collect::<Vec<String>>();
+        .filter_map(|vpc| {
+            vpc.vpc_id()
+                .map(|vpc_id| (vpc_id.to_string(), name_tag(vpc.tags())))
+        })
+        .collect::<Vec<(String, String)>>();
 
-    let _my_vpc = vpc_list.get(0).expect("You have no VPCs in this region");
+    if vpc_list.is_empty() {
+        return Err(Error::MisconfiguredVpc(format!(
+            "You have no VPCs in {}",
+            my_region
+        )));
+    }
 
     let mut valid_subnets: Vec<ValidSubnet> = vec![];
-    for vpc in vpc_list {
+    for (vpc, vpc_name) in vpc_list {
+        let vpc_filter = || Filter::builder().name("vpc-id").values(vpc).build();
         let private_subnets = &client
             .describe_subnets()
-            .filters(Filter::builder().name("vpc-id").values(vpc).build())
+            .filters(vpc_filter())
             .send()
             .await
-            .expect("failed to get subnets")
+            .context("list the subnets")?
             .subnets()
-            .unwrap()
+            .unwrap_or_default()
             .iter()
             .filter(|subnet| {
-                subnet.map_public_ip_on_launch().unwrap() == false
-                    && subnet.available_ip_address_count().unwrap() > 0
+                !subnet.map_public_ip_on_launch().unwrap_or(false)
+                    && subnet.available_ip_address_count().unwrap_or(0) > 0
             })
-            .map(|subnet| subnet.subnet_id().unwrap().to_string())
-            .collect::<Vec<String>>();
+            .cloned()
+            .collect::<Vec<_>>();
+        if private_subnets.is_empty() {
+            continue;
+        }
         let nat_gatways = &client
             .describe_nat_gateways()
-            .filter(Filter::builder().name("vpc-id").values(vpc).build())
+            .filter(vpc_filter())
             .send()
             .await
-            .expect("failed to get nat gateways")
+            .context("list the NAT gateways")?
             .nat_gateways()
-            .unwrap()
+            .unwrap_or_default()
             .iter()
-            .map(|nat_gateway| nat_gateway.nat_gateway_id().unwrap().to_string())
+            .filter_map(|nat_gateway| {
+                nat_gateway
+                    .nat_gateway_id()
+                    .map(|nat_gateway_id| nat_gateway_id.to_string())
+            })
             .collect::<Vec<String>>();
-        // wepossibly have some private subnets and nat gateways in this vpc
-        if private_subnets.len() > 0 && nat_gatways.len() > 0 {
-            // check if we have a route table with a route to the nat gateway
-            for private_subnet in private_subnets {
-                // get all route tables associated with the subnet
-                let route_tables = &client
-                    .describe_route_tables()
-                    .filters(
-                        Filter::builder()
-                            .name("association.subnet-id")
-                            .values(private_subnet)
-                            .build(),
-                    )
-                    .send()
-                    .await
-                    .expect("failed to get route tables")
-                    .route_tables()
-                    .unwrap()
-                    .iter()
-                    .filter_map(|route_table| match route_table.route_table_id() {
-                        Some(route_table_id) => Some(route_table_id.to_string()),
-                        None => None,
-                    })
-                    .collect::<Vec<String>>();
-
-                // check if the route table has a route to the nat gateway
-                for route_table in route_tables {
-                    let routes = &client
-                        .describe_route_tables()
-                        .route_table_ids(route_table)
-                        .send()
-                        .await
-                        .expect("failed to get routes")
-                        .route_tables()
-                        .unwrap_or(&vec![])
-                        .iter()
-                        .map(|route_table| {
-                            route_table
-                                .routes()
-                                .unwrap_or(&vec![])
-                                .iter()
-                                .filter_map(|route| match route.nat_gateway_id() {
-                                    Some(nat_gateway_id) => Some(nat_gateway_id.to_string()),
-                                    None => None,
-                                })
-                                .collect::<Vec<String>>()
-                        })
-                        .collect::<Vec<Vec<String>>>();
-
-                    for route in routes {
-                        for nat_gateway in nat_gatways {
-                            if route.contains(nat_gateway) {
-                                valid_subnets.push(ValidSubnet::new(
-                                    vpc.to_string(),
-                                    private_subnet.to_string(),
-                                ))
-                            };
-                        }
-                    }
-                }
+        let route_tables = client
+            .describe_route_tables()
+            .filters(vpc_filter())
+            .send()
+            .await
+            .context("list the route tables")?
+            .route_tables()
+            .unwrap_or_default()
+            .to_vec();
+        let endpoints = client
+            .describe_vpc_endpoints()
+            .filters(vpc_filter())
+            .filters(
+                Filter::builder()
+                    .name("vpc-endpoint-state")
+                    .values("available")
+                    .build(),
+            )
+            .send()
+            .await
+            .context("list the VPC endpoints")?
+            .vpc_endpoints()
+            .unwrap_or_default()
+            .to_vec();
+
+        for private_subnet in private_subnets {
+            let subnet_id = private_subnet.subnet_id().unwrap_or_default();
+            let egress = subnet_route_table(&route_tables, subnet_id).and_then(|route_table| {
+                subnet_egress(route_table, nat_gatways, &endpoints, my_region)
+            });
+            if let Some(egress) = egress {
+                valid_subnets.push(ValidSubnet {
+                    vpc: vpc.to_string(),
+                    vpc_name: vpc_name.to_string(),
+                    subnet: subnet_id.to_string(),
+                    name: name_tag(private_subnet.tags()),
+                    availability_zone: private_subnet
+                        .availability_zone()
+                        .unwrap_or_default()
+                        .to_string(),
+                    available_ips: private_subnet.available_ip_address_count().unwrap_or(0),
+                    egress,
+                });
             }
         }
     }
-    if valid_subnets.len() == 0 {
-        println!(
-            "{}",
-            "No suitable subnets found. Please create a private subnet with a route to a NAT gateway in the VPC where the source data is stored"
-                .red()
-        );
-        std::process::exit(1);
+    if valid_subnets.is_empty() {
+        return Err(Error::MisconfiguredVpc(format!(
+            "No private subnet with a route to a NAT gateway or with VPC endpoints for {} found in {}",
+            ENDPOINT_SERVICES.join(", "),
+            my_region
+        )));
     }
     Ok(valid_subnets)
 }
 
-/// Returns a suitable AMI for the region specified in the environment or default region
-/// The AMI is the latest Amazon Linux 2 AMI
-/// The AMI is used to create the EC2 instance to run the workload
-async fn get_suitable_ami(my_region: &str) -> Result<String, Error> {
-    let client = get_ec2_client(my_region).await;
-    let suitable_ami = client
+/// Returns the AMI to create the EC2 instance from and the name of its root device.
+/// Defaults to the latest Amazon Linux 2023 AMI of the architecture, which EC2 resolves from its
+/// public SSM parameter at launch. An AMI id given with --ami is looked up, a hardened image may
+/// use another root device and must match the architecture of the instance type.
+async fn get_suitable_ami(
+    ctx: &AwsContext,
+    my_region: &str,
+    options: &JobOptions,
+) -> Result<(String, String)> {
+    let ami = match &options.ami {
+        Some(ami) if ami.starts_with("ami-") => ami,
+        // custom SSM parameters are resolved by EC2 as well
+        Some(ami) => return Ok((ami.clone(), AMAZON_LINUX_ROOT_DEVICE.to_string())),
+        None => {
+            return Ok((
+                format!("{}{}", AL2023_AMI_PARAMETER, options.architecture.as_str()),
+                AMAZON_LINUX_ROOT_DEVICE.to_string(),
+            ))
+        }
+    };
+    let client = ctx.ec2(my_region);
+    let action = format!("read AMI {}", ami);
+    let output = client
         .describe_images()
-        .owners("amazon")
-        .filters(
-            Filter::builder()
-                .name("name")
-                .values("amzn2-ami-hvm-*-x86_64-gp2")
-                .build(),
-        )
+        .image_ids(ami)
         .send()
         .await
-        .expect("failed to get images")
+        .context(&action)?;
+    let image = output
         .images()
-        .unwrap()
-        .get(0)
-        .unwrap()
-        .image_id()
-        .unwrap()
-        .to_string();
-    Ok(suitable_ami)
+        .and_then(|images| images.first())
+        .ok_or_else(|| Error::NotFound(format!("AMI {} not found in {}", ami, my_region)))?;
+    let architecture = image
+        .architecture()
+        .map(|architecture| architecture.as_str())
+        .unwrap_or_default();
+    if architecture != options.architecture.as_str() {
+        return Err(Error::Config(format!(
+            "AMI {} is built for {}, not for the {} instance type {}. Pass --arch {} or another AMI",
+            ami,
+            architecture,
+            options.architecture.as_str(),
+            options.instance_type.as_str(),
+            architecture
+        )));
+    }
+    let root_device_name = image
+        .root_device_name()
+        .map(|root_device_name| root_device_name.to_string())
+        .ok_or_else(|| Error::unexpected_response(&action, "root device name"))?;
+    Ok((ami.clone(), root_device_name))
+}
+
+/// Checks that the instance type runs on the architecture, an arm64 AMI does not boot on an
+/// Intel instance. Not checked for simulated instances.
+pub async fn check_instance_architecture(
+    ctx: &AwsContext,
+    region: &str,
+    instance_type: &InstanceType,
+    architecture: Architecture,
+) -> Result<()> {
+    if ctx.simulate_instance() {
+        return Ok(());
+    }
+    let action = format!("describe the instance type {}", instance_type.as_str());
+    let output = ctx
+        .ec2(region)
+        .describe_instance_types()
+        .instance_types(instance_type.clone())
+        .send()
+        .await
+        .context(&action)?;
+    let supported_architectures = output
+        .instance_types()
+        .and_then(|instance_types| instance_types.first())
+        .and_then(|instance_type| instance_type.processor_info())
+        .and_then(|processor_info| processor_info.supported_architectures())
+        .ok_or_else(|| {
+            Error::NotFound(format!(
+                "Instance type {} is not offered in {}",
+                instance_type.as_str(),
+                region
+            ))
+        })?;
+    if supported_architectures
+        .iter()
+        .any(|supported| supported.as_str() == architecture.as_str())
+    {
+        return Ok(());
+    }
+    Err(Error::Config(format!(
+        "Instance type {} does not run {} workers, pass an instance type of that architecture \
+        or --arch {}",
+        instance_type.as_str(),
+        architecture.as_str(),
+        supported_architectures
+            .first()
+            .map(|supported| supported.as_str())
+            .unwrap_or_default()
+    )))
 }
 
 /// Returns a script to be run on the EC2 instance that generates the synthetic data
-fn get_script(glue_table: &GlueTable) -> String {
+fn get_script(glue_table: &GlueTable, options: &JobOptions) -> String {
     let bash_script = include_str!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/src/scripts/ec2_bash.sh"
@@ -208,9 +550,42 @@ fn get_script(glue_table: &GlueTable) -> String {
         "/src/scripts/single_table.py"
     ));
 
+    let wheelhouse = options.wheelhouse.as_ref();
+
+    // longer placeholders go first as "<your table>" is a prefix of "<your table format>"
     let script = bash_script
         .replace("<your python script>", python_script)
+        .replace("<your table format>", &format!("{:?}", glue_table.format()))
+        .replace("<your model>", &options.model.to_string())
+        .replace(
+            "<your wheelhouse sha256>",
+            wheelhouse
+                .and_then(|wheelhouse| wheelhouse.sha256.as_deref())
+                .unwrap_or_default(),
+        )
+        .replace(
+            "<your wheelhouse>",
+            wheelhouse
+                .map(|wheelhouse| wheelhouse.location.as_str())
+                .unwrap_or_default(),
+        )
+        .replace("<your requirements>", REQUIREMENTS.trim_end())
+        .replace("<your table location>", glue_table.s3_location())
+        .replace("<your output location>", &glue_table.output_location())
+        .replace(
+            "<your connection>",
+            glue_table
+                .connection()
+                .map(|connection| connection.name().as_str())
+                .unwrap_or("-"),
+        )
+        .replace(
+            "<your sample size>",
+            &glue_table.sample_size().unwrap_or(0).to_string(),
+        )
         .replace("<your database>", glue_table.database().name())
+        .replace("<your query database>", glue_table.database().query_name())
+        .replace("<your catalog id>", glue_table.database().catalog_id())
         .replace("<your table>", glue_table.name())
         .replace("<your project>", PROJECT_NAME);
 
@@ -219,34 +594,57 @@ fn get_script(glue_table: &GlueTable) -> String {
 
 /// Issues a request to create an EC2 instance with the specified AMI and runs the script on it
 /// Returns the instance id of the created instance
-async fn run_ec2_instance(subnet_id: &str, glue_table: &GlueTable) -> Result<String, Error> {
+async fn run_ec2_instance(
+    ctx: &AwsContext,
+    subnet_id: &str,
+    glue_table: &GlueTable,
+    options: &JobOptions,
+    security_group_id: Option<&str>,
+) -> Result<String> {
     // get all the required parameters
     let my_region = glue_table.database().region();
-    let latest_ami = get_suitable_ami(my_region).await?;
-    let script = get_script(glue_table);
+    let (ami, root_device_name) = get_suitable_ami(ctx, my_region, options).await?;
+    let script = get_script(glue_table, options);
     let tag = Tag::builder().key("Name").value(PROJECT_NAME).build();
-    let client = get_ec2_client(my_region).await;
+    let client = ctx.ec2(my_region);
+    // JDBC sources are reached through the security groups of their Glue connection
+    let security_group_ids = match glue_table.connection() {
+        Some(connection) => connection.security_group_ids().clone(),
+        None => security_group_id
+            .map(|id| id.to_string())
+            .into_iter()
+            .collect(),
+    };
 
     // TODO: change this to take table structure as input
-    let iam_instance_profile = get_iam_instance_profile_specification(glue_table)
-        .await
-        .unwrap();
+    let iam_instance_profile =
+        get_iam_instance_profile_specification(ctx, glue_table, options.wheelhouse_location())
+            .await?;
     // let iam_instance_profile = IamInstanceProfileSpecification::builder()
     //    .arn("arn:aws:iam::050532831725:instance-profile/PowerUser")
     //    .build();
 
     // create instance and get instance id of it
 
-    let instance_id = client
+    let action = "launch the EC2 instance";
+    let request = client
         .run_instances()
-        .image_id(latest_ami.to_string())
-        .instance_type(InstanceType::C6i4xlarge)
+        .image_id(ami)
+        .instance_type(options.instance_type.clone())
         .max_count(1)
         .min_count(1)
         .block_device_mappings(
             BlockDeviceMapping::builder()
-                .device_name("/dev/xvda")
-                .ebs(EbsBlockDevice::builder().volume_size(1000).build())
+                .device_name(root_device_name)
+                .ebs(
+                    EbsBlockDevice::builder()
+                        .volume_size(options.volume_size)
+                        .volume_type(options.volume_type.clone())
+                        .encrypted(options.encrypted)
+                        .set_kms_key_id(options.kms_key_id.clone())
+                        .delete_on_termination(true)
+                        .build(),
+                )
                 .build(),
         )
         .tag_specifications(
@@ -256,131 +654,532 @@ async fn run_ec2_instance(subnet_id: &str, glue_table: &GlueTable) -> Result<Str
                 .build(),
         )
         .subnet_id(subnet_id)
+        .set_security_group_ids(Some(security_group_ids))
         .iam_instance_profile(iam_instance_profile)
-        .user_data(&script)
-        .send()
-        .await
-        .expect("failed to create instance")
-        .instances
-        .unwrap()
-        .get(0)
-        .unwrap()
-        .instance_id()
-        .unwrap()
-        .to_string();
-
-    Ok(instance_id)
+        // IMDSv2 only, and the hop limit keeps the credentials of the instance out of containers
+        .metadata_options(
+            InstanceMetadataOptionsRequest::builder()
+                .http_endpoint(InstanceMetadataEndpointState::Enabled)
+                .http_tokens(HttpTokensState::Required)
+                .http_put_response_hop_limit(1)
+                .build(),
+        )
+        .user_data(&script);
+    // one-time spot requests end with the instance, interruptions terminate it
+    let request = if options.spot {
+        request.instance_market_options(
+            InstanceMarketOptionsRequest::builder()
+                .market_type(MarketType::Spot)
+                .spot_options(
+                    SpotMarketOptions::builder()
+                        .spot_instance_type(SpotInstanceType::OneTime)
+                        .instance_interruption_behavior(InstanceInterruptionBehavior::Terminate)
+                        .build(),
+                )
+                .build(),
+        )
+    } else {
+        request
+    };
+
+    // EC2 sees a new instance profile a few seconds after IAM does, launches are retried until then
+    let response = retry_while(
+        INSTANCE_PROFILE_PROPAGATION_TIMEOUT,
+        is_instance_profile_not_propagated,
+        || async { request.clone().send().await.context(action) },
+    )
+    .await?;
+    response
+        .instances()
+        .and_then(|instances| instances.first())
+        .and_then(|instance| instance.instance_id())
+        .map(|instance_id| instance_id.to_string())
+        .ok_or_else(|| Error::unexpected_response(action, "instance id"))
+}
+
+/// Checks if a launch failed because EC2 does not see the instance profile yet
+fn is_instance_profile_not_propagated(err: &Error) -> bool {
+    matches!(err, Error::Aws { code, message, .. }
+        if code == "InvalidParameterValue" && message.contains("iamInstanceProfile"))
 }
 
-/// Returns the instance state name of the specified instance
+/// Simulates the worker instance for runs against AWS emulators that cannot boot instances.
+/// The IAM resources are created as for a real instance and the worker log lines are sent
+/// to CloudWatch logs, so the job runs end to end without generating data.
+/// Returns the task sending the log lines.
+async fn simulate_ec2_instance(
+    ctx: &AwsContext,
+    glue_table: &GlueTable,
+    options: &JobOptions,
+) -> Result<SimulatedWorker> {
+    get_iam_instance_profile_specification(ctx, glue_table, options.wheelhouse_location()).await?;
+
+    let ctx = ctx.clone();
+    let my_region = glue_table.database().region().to_string();
+    let log_stream_name = glue_table.name().to_string();
+    Ok(tokio::spawn(async move {
+        let logger = CWLogSender::new(&ctx, &my_region, log_stream_name).await?;
+        for message in SIMULATED_WORKER_LOGS {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            logger.send_log(message).await?;
+        }
+        Ok(())
+    }))
+}
+
+/// Returns the instance state name of the specified instance,
+/// with the code of the reason it was stopped or terminated
 async fn get_instance_state_name(
+    ctx: &AwsContext,
     instance_id: &str,
     my_region: &str,
-) -> Result<InstanceStateName, Error> {
-    let client = get_ec2_client(my_region).await;
-    let instance_state_name = &client
+) -> Result<(InstanceStateName, Option<String>)> {
+    // the simulated instance runs until the job is complete
+    if ctx.simulate_instance() {
+        return Ok((InstanceStateName::Running, None));
+    }
+    let client = ctx.ec2(my_region);
+    let action = format!("read the state of instance {}", instance_id);
+    let response = client
         .describe_instances()
         .instance_ids(instance_id.to_string())
         .send()
         .await
-        .expect("failed to get instance")
+        .context(&action)?;
+    let instance = response
         .reservations()
-        .unwrap()
-        .get(0)
-        .unwrap()
-        .instances()
-        .unwrap()
-        .get(0)
-        .unwrap()
+        .and_then(|reservations| reservations.first())
+        .and_then(|reservation| reservation.instances())
+        .and_then(|instances| instances.first())
+        .ok_or_else(|| Error::unexpected_response(&action, "instance"))?;
+    let state_name = instance
         .state()
-        .unwrap()
-        .name()
-        .unwrap()
-        .clone();
-
-    Ok(instance_state_name.clone())
+        .and_then(|state| state.name())
+        .cloned()
+        .ok_or_else(|| Error::unexpected_response(&action, "instance state"))?;
+    let state_reason = instance
+        .state_reason()
+        .and_then(|reason| reason.code())
+        .map(|code| code.to_string());
+    Ok((state_name, state_reason))
 }
 
 /// Runs the synthetic data job creation on ec2 instance using the specified parameters
 /// Job uses the specified database and table as the source
 /// Outputs the progress of the job to CloudWatch logs and displays it on the console
-/// Returns an error if the job fails
-pub async fn run_sythetic_data_job(subnet_id: &str, glue_table: &GlueTable) -> Result<(), Error> {
+/// Returns an error if the job fails. The instance, its security group and the IAM resources are
+/// removed when the job completes or the CLI hits an error, a failed job keeps them for inspection.
+pub async fn run_sythetic_data_job(
+    ctx: &AwsContext,
+    subnet_id: &str,
+    glue_table: &GlueTable,
+    options: &JobOptions,
+) -> Result<()> {
     // Declare a CloudWatch log "helper" for this task
     let my_region = glue_table.database().region();
-    let logger = CWLogSender::new(my_region.into(), glue_table.name().into()).await;
+    let logger = CWLogSender::new(ctx, my_region, glue_table.name().into()).await?;
     // Create a progress bar
-    let mut pb = ProgressTracker::new(logger, 10, &glue_table);
+    let mut pb = ProgressTracker::new(logger, 10, glue_table);
+
+    // tables on S3 get a security group of their own, a relaunch reuses it.
+    // A simulated instance needs none.
+    let security_group_id = match glue_table.connection() {
+        Some(_) => None,
+        None if ctx.simulate_instance() => None,
+        None => match create_job_security_group(ctx, subnet_id, glue_table).await {
+            Ok(security_group_id) => Some(security_group_id),
+            Err(err) => {
+                teardown(ctx, None, None, glue_table).await;
+                return Err(err);
+            }
+        },
+    };
+    let security_group_id = security_group_id.as_deref();
+
+    let mut options = options.clone();
+    let mut simulated_worker = None;
+    loop {
+        // create ec2 instance and get instance id
+        let launched = if ctx.simulate_instance() {
+            simulate_ec2_instance(ctx, glue_table, &options)
+                .await
+                .map(|worker| {
+                    simulated_worker = Some(worker);
+                    SIMULATED_INSTANCE_ID.to_string()
+                })
+        } else {
+            run_ec2_instance(ctx, subnet_id, glue_table, &options, security_group_id).await
+        };
+        let instance_id = match launched {
+            Ok(instance_id) => instance_id,
+            Err(err) => {
+                teardown(ctx, None, security_group_id, glue_table).await;
+                return Err(err);
+            }
+        };
+
+        match wait_for_job(ctx, &instance_id, my_region, &mut pb, &mut simulated_worker).await {
+            // the job starts over on capacity EC2 does not reclaim
+            Ok(JobState::Interrupted) if options.on_interruption == OnInterruption::Relaunch => {
+                pb.set_message(format!(
+                    "Spot instance {} was interrupted, relaunching the job on demand ...",
+                    instance_id
+                ));
+                options.spot = false;
+            }
+            Ok(JobState::Interrupted) => {
+                pb.failed();
+                teardown(ctx, Some(&instance_id), security_group_id, glue_table).await;
+                return Err(Error::Interrupted(format!(
+                    "Synthetic Data Generation Job Interrupted. \
+                    \nEC2 reclaimed the spot instance {} before the job completed.",
+                    instance_id
+                )));
+            }
+            result => {
+                return finish_job(ctx, &instance_id, security_group_id, glue_table, result).await
+            }
+        }
+    }
+}
 
-    // create ec2 instance and get instance id
-    let instance_id = run_ec2_instance(subnet_id, glue_table).await?;
+/// Reports the end of the job and removes its resources, except those of a failed job
+async fn finish_job(
+    ctx: &AwsContext,
+    instance_id: &str,
+    security_group_id: Option<&str>,
+    glue_table: &GlueTable,
+    result: Result<JobState>,
+) -> Result<()> {
+    match result {
+        Ok(JobState::Completed) => {
+            teardown(ctx, Some(instance_id), security_group_id, glue_table).await;
+            let summary_message = format!(
+                "Synthetic Data Generation Job Completed. \
+                \nPlease check the database {} and table {} for the generated data.",
+                glue_table.database().query_name(),
+                glue_table.output_table_name()
+            );
+            println!("{}", summary_message.green());
+            Ok(())
+        }
+        Ok(_) => Err(Error::JobFailed(format!(
+            "Synthetic Data Generation Job Failed. \
+            \nPlease check logs on CloudWatch - {} and Instance - {} for more details.{}",
+            PROJECT_NAME,
+            instance_id,
+            security_group_id
+                .map(|id| format!(" Delete its security group {} once it is terminated.", id))
+                .unwrap_or_default()
+        ))),
+        Err(err) => {
+            teardown(ctx, Some(instance_id), security_group_id, glue_table).await;
+            Err(err)
+        }
+    }
+}
 
-    // wait for the instance to fail or complete the job.
-    // Terminate the instance once the job is complete or failed
+/// Waits for the instance to fail or complete the job and returns the final state of the job
+async fn wait_for_job(
+    ctx: &AwsContext,
+    instance_id: &str,
+    my_region: &str,
+    pb: &mut ProgressTracker,
+    simulated_worker: &mut Option<SimulatedWorker>,
+) -> Result<JobState> {
     loop {
+        // a simulated worker that could not send its log lines never writes the end of the job
+        if let Some(worker) = simulated_worker.take_if(|worker| worker.is_finished()) {
+            let result = worker.await.unwrap_or_else(|err| {
+                Err(Error::JobFailed(format!(
+                    "The simulated worker stopped: {}",
+                    err
+                )))
+            });
+            if let Err(err) = result {
+                pb.failed();
+                return Err(err);
+            }
+        }
         // get the instance state name
-        let instance_state_name = get_instance_state_name(&instance_id, my_region).await?;
+        let (instance_state_name, state_reason) =
+            get_instance_state_name(ctx, instance_id, my_region).await?;
 
         match instance_state_name {
             // if the instance is running, update the progress bar
-            InstanceStateName::Running => {
-                let state = pb.update_progress().await;
-                // if the job is completed, terminate the instance and break the loop
-                match state {
-                    JobState::Completed => {
-                        // terminate ec2 instance
-                        terminate_ec2_instance(&instance_id, my_region).await?;
-                        // clean up iam role
-                        cleanup_aim(glue_table)
-                            .await
-                            .expect("failed to clean up iam role");
-
-                        let summary_message = format!(
-                            "Synthetic Data Generation Job Completed. \
-                            \nPlease check the database {} and table {}_synthetic for the generated data.",
-                            glue_table.database().name(), glue_table.name()
-                        );
-                        println!("{}", summary_message.green());
-                        break;
-                    }
-                    // if the job is running, continue the loop
-                    JobState::Running => {}
-                    // if the job is failed, terminate the instance and break the loop
-                    JobState::Failed => {
-                        // terminate_ec2_instance(&instance_id, my_region).await?;
-                        let summary_message = format!(
-                            "Synthetic Data Generation Job Failed. \
-                            \nPlease check logs on CloudWatch - {} and Instance - {} for more details.",
-                            PROJECT_NAME, instance_id
-                        );
-                        println!("{}", summary_message.red().bold());
-                        break;
-                    }
-                }
+            InstanceStateName::Running => match pb.update_progress().await? {
+                // if the job is running, continue the loop
+                JobState::Running => {}
+                state => return Ok(state),
+            },
+            // if the instance is pending, wait for it to boot before reading its state again
+            InstanceStateName::Pending => tokio::time::sleep(PENDING_POLL_INTERVAL).await,
+            // EC2 reclaimed the spot instance
+            _ if state_reason
+                .as_deref()
+                .is_some_and(|code| code.starts_with(SPOT_INTERRUPTION_REASON)) =>
+            {
+                return Ok(JobState::Interrupted);
             }
-            // if the instance is pending, continue the loop
-            InstanceStateName::Pending => {}
-            // if the instance is terminated, break the loop
-            _ => {
-                println!("Instance is in an unknown state");
-                break;
+            // if the instance is stopped or terminated the job cannot complete
+            state => {
+                pb.failed();
+                println!(
+                    "Instance {} is {} ({}), the job cannot complete",
+                    instance_id,
+                    state.as_str(),
+                    state_reason.as_deref().unwrap_or("no reason given")
+                );
+                return Ok(JobState::Failed);
             }
         }
     }
+}
 
-    Ok(())
+/// Removes the instance, its security group and the IAM resources of the job.
+/// Runs on the error path too, so failures are reported without hiding the original error.
+async fn teardown(
+    ctx: &AwsContext,
+    instance_id: Option<&str>,
+    security_group_id: Option<&str>,
+    glue_table: &GlueTable,
+) {
+    let my_region = glue_table.database().region();
+    if let Some(instance_id) = instance_id {
+        if let Err(err) = terminate_ec2_instance(ctx, instance_id, my_region).await {
+            eprintln!(
+                "{}",
+                format!("Could not terminate instance {}: {}", instance_id, err).yellow()
+            );
+        }
+    }
+    // the group is released once the instance is terminated, which takes a minute or two
+    if let Some(security_group_id) = security_group_id {
+        if let Err(err) = delete_job_security_group(ctx, my_region, security_group_id).await {
+            eprintln!(
+                "{}",
+                format!(
+                    "Could not delete security group {}: {}",
+                    security_group_id, err
+                )
+                .yellow()
+            );
+        }
+    }
+    if let Err(err) = cleanup_aim(ctx, glue_table).await {
+        eprintln!(
+            "{}",
+            format!("Could not clean up the IAM role {}: {}", PROJECT_NAME, err).yellow()
+        );
+    }
 }
 
 /// Terminates the ec2 instance with the specified instance id
-async fn terminate_ec2_instance(instance_id: &str, my_region: &str) -> Result<(), Error> {
-    let client = get_ec2_client(my_region).await;
+async fn terminate_ec2_instance(
+    ctx: &AwsContext,
+    instance_id: &str,
+    my_region: &str,
+) -> Result<()> {
+    if ctx.simulate_instance() {
+        return Ok(());
+    }
+    let client = ctx.ec2(my_region);
 
     client
         .terminate_instances()
         .instance_ids(instance_id.to_string())
         .send()
         .await
-        .expect("failed to terminate instance");
+        .context(&format!("terminate instance {}", instance_id))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_ec2::model::{Route, RouteTableAssociation};
+
+    fn route_table(
+        id: &str,
+        subnet_id: Option<&str>,
+        main: bool,
+        nat_gateway_id: Option<&str>,
+    ) -> RouteTable {
+        let association = RouteTableAssociation::builder()
+            .set_subnet_id(subnet_id.map(|id| id.to_string()))
+            .main(main)
+            .build();
+        let route = Route::builder()
+            .set_nat_gateway_id(nat_gateway_id.map(|id| id.to_string()))
+            .build();
+        RouteTable::builder()
+            .route_table_id(id)
+            .associations(association)
+            .routes(route)
+            .build()
+    }
+
+    fn gateway_endpoint(service: &str, route_table_id: &str) -> VpcEndpoint {
+        VpcEndpoint::builder()
+            .service_name(format!("com.amazonaws.eu-west-1.{}", service))
+            .vpc_endpoint_type(VpcEndpointType::Gateway)
+            .route_table_ids(route_table_id)
+            .build()
+    }
+
+    fn interface_endpoint(service: &str, private_dns: bool) -> VpcEndpoint {
+        VpcEndpoint::builder()
+            .service_name(format!("com.amazonaws.eu-west-1.{}", service))
+            .vpc_endpoint_type(VpcEndpointType::Interface)
+            .private_dns_enabled(private_dns)
+            .build()
+    }
+
+    /// S3 gateway endpoint on the route table and interface endpoints for the other services
+    fn all_endpoints(route_table_id: &str) -> Vec<VpcEndpoint> {
+        ENDPOINT_SERVICES
+            .iter()
+            .map(|service| match *service {
+                "s3" => gateway_endpoint(service, route_table_id),
+                service => interface_endpoint(service, true),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn subnet_uses_its_own_route_table_first() {
+        let route_tables = [
+            route_table("rtb-main", None, true, None),
+            route_table("rtb-own", Some("subnet-1"), false, None),
+        ];
+        let route_table = subnet_route_table(&route_tables, "subnet-1").unwrap();
+        assert_eq!(route_table.route_table_id(), Some("rtb-own"));
+    }
+
+    #[test]
+    fn subnet_falls_back_to_the_main_route_table() {
+        let route_tables = [
+            route_table("rtb-other", Some("subnet-2"), false, None),
+            route_table("rtb-main", None, true, None),
+        ];
+        let route_table = subnet_route_table(&route_tables, "subnet-1").unwrap();
+        assert_eq!(route_table.route_table_id(), Some("rtb-main"));
+        assert!(subnet_route_table(&route_tables[..1], "subnet-1").is_none());
+    }
+
+    #[test]
+    fn nat_route_gives_a_nat_gateway_egress() {
+        let route_table = route_table("rtb-1", Some("subnet-1"), false, Some("nat-1"));
+        let nat_gateways = ["nat-1".to_string()];
+        assert_eq!(
+            subnet_egress(&route_table, &nat_gateways, &[], "eu-west-1"),
+            Some(SubnetEgress::NatGateway)
+        );
+        // a route to a deleted NAT gateway is a black hole
+        assert_eq!(subnet_egress(&route_table, &[], &[], "eu-west-1"), None);
+    }
+
+    #[test]
+    fn every_service_needs_an_endpoint() {
+        let route_table = route_table("rtb-1", Some("subnet-1"), false, None);
+        assert_eq!(
+            subnet_egress(&route_table, &[], &all_endpoints("rtb-1"), "eu-west-1"),
+            Some(SubnetEgress::VpcEndpoints)
+        );
+        for missing in ENDPOINT_SERVICES {
+            let endpoints = all_endpoints("rtb-1")
+                .into_iter()
+                .filter(|endpoint| !endpoint.service_name().unwrap().ends_with(missing))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                subnet_egress(&route_table, &[], &endpoints, "eu-west-1"),
+                None,
+                "{}",
+                missing
+            );
+        }
+    }
+
+    #[test]
+    fn gateway_endpoint_must_be_on_the_route_table() {
+        let endpoints = [gateway_endpoint("s3", "rtb-2")];
+        assert!(!has_vpc_endpoint(&endpoints, "eu-west-1", "s3", "rtb-1"));
+        assert!(has_vpc_endpoint(&endpoints, "eu-west-1", "s3", "rtb-2"));
+    }
+
+    #[test]
+    fn interface_endpoint_needs_private_dns() {
+        assert!(!has_vpc_endpoint(
+            &[interface_endpoint("glue", false)],
+            "eu-west-1",
+            "glue",
+            "rtb-1"
+        ));
+        assert!(has_vpc_endpoint(
+            &[interface_endpoint("glue", true)],
+            "eu-west-1",
+            "glue",
+            "rtb-1"
+        ));
+    }
+
+    #[test]
+    fn endpoint_must_be_in_the_region() {
+        let endpoints = [interface_endpoint("glue", true)];
+        assert!(!has_vpc_endpoint(
+            &endpoints,
+            "eu-central-1",
+            "glue",
+            "rtb-1"
+        ));
+    }
+
+    fn subnet(name: &str, vpc_name: &str) -> ValidSubnet {
+        ValidSubnet {
+            vpc: "vpc-1".to_string(),
+            vpc_name: vpc_name.to_string(),
+            subnet: "subnet-1".to_string(),
+            name: name.to_string(),
+            availability_zone: "eu-west-1a".to_string(),
+            available_ips: 251,
+            egress: SubnetEgress::VpcEndpoints,
+        }
+    }
+
+    #[test]
+    fn subnet_record_round_trips() {
+        let subnet = subnet("private\ta", "");
+        let record = ValidSubnet::from_fields(&subnet.to_fields()).unwrap();
+        assert_eq!(record.to_fields(), subnet.to_fields());
+        assert_eq!(record.get_egress(), SubnetEgress::VpcEndpoints);
+    }
+
+    #[test]
+    fn subnet_record_rejects_invalid_fields() {
+        let fields = subnet("private-a", "main").to_fields();
+        assert!(ValidSubnet::from_fields(&fields[..6]).is_none());
+        for (index, value) in [(5, "many"), (6, "Internet gateway")] {
+            let mut fields = fields.clone();
+            fields[index] = value.to_string();
+            assert!(ValidSubnet::from_fields(&fields).is_none(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn subnet_display_shows_names_when_tagged() {
+        assert_eq!(
+            subnet("private-a", "main").format_for_display(),
+            "private-a (subnet-1)  eu-west-1a  251 free IPs  VPC main (vpc-1)  through VPC endpoints"
+        );
+        let mut untagged = subnet("", "");
+        untagged.egress = SubnetEgress::NatGateway;
+        assert_eq!(
+            untagged.format_for_display(),
+            format!(
+                "subnet-1  eu-west-1a  251 free IPs  VPC vpc-1  through {}",
+                SubnetEgress::NatGateway
+            )
+        );
+    }
+}
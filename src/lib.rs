@@ -11,13 +11,37 @@
 //! ./synthetic_data_generator
 //! ```
 //!
+//! To only search some regions for Glue databases, or to use a named profile:
+//!
+//! ```bash
+//! ./synthetic_data_generator --profile analytics --regions eu-west-1,eu-central-1
+//! ```
+//!
+//! To read source data that lives in another account, pass a role to assume:
+//!
+//! ```bash
+//! ./synthetic_data_generator --role-arn arn:aws:iam::123456789012:role/DataLakeReader --external-id my-id
+//! ```
+//!
 //! Follow the prompts to generate data for a single table or multiple tables that are stored in AWS Glue.
 
 // pub mod aws_common;
 pub const PROJECT_NAME: &str = "SynthTable";
+mod athena;
+mod aws_context;
+mod aws_credentials;
+mod bundle;
+mod cache;
+pub mod cli;
+mod config;
 mod cw_logging;
+pub mod error;
 mod get_glue_data;
 mod get_processing_job;
 mod manage_iam;
+mod manage_security_group;
+mod pricing;
 mod progress_tracker;
 pub mod prompts;
+mod retry;
+mod sizing;
@@ -1,9 +1,18 @@
-use std::error::Error;
+use std::process::ExitCode;
 
+use clap::Parser;
+use colored::*;
+use synth_table::cli::Cli;
 use synth_table::prompts::run_workflow;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    run_workflow().await?;
-    Ok(())
+async fn main() -> ExitCode {
+    match run_workflow(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err.to_string().red().bold());
+            eprintln!("{}", err.guidance());
+            ExitCode::from(err.exit_code())
+        }
+    }
 }
@@ -4,63 +4,66 @@
 //! it is used by the CLI to create and delete IAM role and instance profile with a minimal set of permissions
 //! required to run the python script on EC2 instance.
 const POLICY_DIR: Dir = include_dir!("src/policies");
-
-use crate::get_glue_data::GlueTable;
+/// Maximum aggregate size of all inline policies attached to a role, whitespace excluded
+const INLINE_POLICY_SIZE_LIMIT: usize = 10240;
+/// Time IAM gets to make a created or deleted role and instance profile visible
+const IAM_CONSISTENCY_TIMEOUT: Duration = Duration::from_secs(120);
+
+use crate::aws_context::AwsContext;
+use crate::error::{Error, Result, ResultExt};
+use crate::get_glue_data::{to_s3_arn, GlueTable};
+use crate::retry::wait_until;
 use crate::PROJECT_NAME;
 use aws_sdk_ec2::model::IamInstanceProfileSpecification;
-use aws_sdk_iam::{Client as IamClient, Error as IamError};
-use aws_types::region::Region;
+use colored::*;
 use include_dir::{include_dir, Dir};
 extern crate include_dir;
 use tokio::time::Duration;
 
-/// get IAM client for the region specified region
-async fn get_iam_client(region: &str) -> Result<IamClient, IamError> {
-    let config = aws_config::from_env()
-        .region(Region::new(region.to_string()))
-        .load()
-        .await;
-    Ok(IamClient::new(&config))
-}
-
 /// Checks if instance profile exists
-async fn is_instance_profile_exists(region: &str) -> Result<bool, IamError> {
-    let client = get_iam_client(region).await?;
+async fn is_instance_profile_exists(ctx: &AwsContext, region: &str) -> Result<bool> {
+    let client = ctx.iam(region);
 
     let is_exists = client
         .list_instance_profiles()
         .send()
         .await
-        .expect("Could not list instance profiles")
+        .context("list instance profiles")?
         .instance_profiles()
-        .unwrap()
+        .unwrap_or_default()
         .iter()
-        .any(|instance_profile| instance_profile.instance_profile_name().unwrap() == PROJECT_NAME);
+        .any(|instance_profile| instance_profile.instance_profile_name() == Some(PROJECT_NAME));
 
     Ok(is_exists)
 }
 
 /// Deletes instance profile
-async fn delete_instance_profile(region: &str) -> Result<(), IamError> {
-    let client = get_iam_client(region).await?;
+async fn delete_instance_profile(ctx: &AwsContext, region: &str) -> Result<()> {
+    let client = ctx.iam(region);
     let _response = client
         .delete_instance_profile()
         .instance_profile_name(PROJECT_NAME)
         .send()
         .await
-        .expect("Could not delete instance profile");
-
-    // loop while instance profile is not actually deleted. This is needed because IAM is eventually consistent
-    while is_instance_profile_exists(region).await? {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
-    Ok(())
+        .context("delete the instance profile")?;
+
+    // wait while instance profile is not actually deleted. This is needed because IAM is eventually consistent
+    wait_until(
+        "the instance profile to be deleted",
+        IAM_CONSISTENCY_TIMEOUT,
+        || async { Ok(!is_instance_profile_exists(ctx, region).await?) },
+    )
+    .await
 }
 
 /// Creates instance profile. Returns instance profile ARN
-async fn create_instance_profile(glue_table: &GlueTable) -> Result<String, IamError> {
+async fn create_instance_profile(
+    ctx: &AwsContext,
+    glue_table: &GlueTable,
+    wheelhouse: Option<&str>,
+) -> Result<String> {
     let region = glue_table.database().region();
-    let client = get_iam_client(region).await?;
+    let client = ctx.iam(region);
 
     // create instance profile
     let response = client
@@ -68,64 +71,95 @@ async fn create_instance_profile(glue_table: &GlueTable) -> Result<String, IamEr
         .instance_profile_name(PROJECT_NAME)
         .send()
         .await
-        .expect("Could not create instance profile");
-
-    // loop while instance profile is not created. This is needed because IAM is eventually consistent
-    while !is_instance_profile_exists(region).await? {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
+        .context("create the instance profile")?;
 
-    tokio::time::sleep(Duration::from_secs(30)).await;
+    // wait while instance profile is not created. This is needed because IAM is eventually consistent
+    wait_until(
+        "the instance profile to be created",
+        IAM_CONSISTENCY_TIMEOUT,
+        || is_instance_profile_exists(ctx, region),
+    )
+    .await?;
 
     // add role to instance profile
-    add_role_to_instance_profile(region).await.unwrap();
+    add_role_to_instance_profile(ctx, region).await?;
+    wait_until(
+        "the role to be added to the instance profile",
+        IAM_CONSISTENCY_TIMEOUT,
+        || is_role_in_instance_profile(ctx, region),
+    )
+    .await?;
 
-    add_policies_to_role(glue_table).await.unwrap();
+    add_policies_to_role(ctx, glue_table, wheelhouse).await?;
 
-    Ok(response
+    response
         .instance_profile()
-        .unwrap()
-        .arn()
-        .unwrap()
-        .to_string())
+        .and_then(|instance_profile| instance_profile.arn())
+        .map(|arn| arn.to_string())
+        .ok_or_else(|| Error::unexpected_response("create the instance profile", "ARN"))
+}
+
+/// Checks if the instance profile lists the role, EC2 can only use the profile once it does
+async fn is_role_in_instance_profile(ctx: &AwsContext, region: &str) -> Result<bool> {
+    let client = ctx.iam(region);
+    let response = client
+        .get_instance_profile()
+        .instance_profile_name(PROJECT_NAME)
+        .send()
+        .await
+        .context("read the instance profile");
+
+    match response {
+        Ok(response) => Ok(response
+            .instance_profile()
+            .and_then(|instance_profile| instance_profile.roles())
+            .unwrap_or_default()
+            .iter()
+            .any(|role| role.role_name() == Some(PROJECT_NAME))),
+        Err(Error::NotFound(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
 }
 
 /// Checks if role exists
-async fn is_role_exists(region: &str) -> Result<bool, String> {
-    let client = get_iam_client(region)
+async fn is_role_exists(ctx: &AwsContext, region: &str) -> Result<bool> {
+    let client = ctx.iam(region);
+    let response = client
+        .get_role()
+        .role_name(PROJECT_NAME)
+        .send()
         .await
-        .expect("Could not get IAM client");
-    let response = &client.get_role().role_name(PROJECT_NAME).send().await;
+        .context("read the role of the instance");
 
     match response {
         Ok(_) => Ok(true),
-        Err(_) => Ok(false),
+        Err(Error::NotFound(_)) => Ok(false),
+        Err(err) => Err(err),
     }
 }
 
 /// Deletes role
-async fn delete_role(region: &str) -> Result<(), IamError> {
-    let client = get_iam_client(region).await?;
+async fn delete_role(ctx: &AwsContext, region: &str) -> Result<()> {
+    let client = ctx.iam(region);
     let _response = client
         .delete_role()
         .role_name(PROJECT_NAME)
         .send()
         .await
-        .expect("Could not delete role");
-
-    // loop while role is not deleted. This is needed because IAM is eventually consistent
-    while !is_role_exists(region)
-        .await
-        .expect("Could not check if role exists")
-    {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
-    Ok(())
+        .context("delete the role of the instance")?;
+
+    // wait while role is not deleted. This is needed because IAM is eventually consistent
+    wait_until(
+        "the role to be deleted",
+        IAM_CONSISTENCY_TIMEOUT,
+        || async { Ok(!is_role_exists(ctx, region).await?) },
+    )
+    .await
 }
 
 /// Creates role for EC2 instance
-async fn create_ec2_role(region: &str) -> Result<(), IamError> {
-    let client = get_iam_client(region).await?;
+async fn create_ec2_role(ctx: &AwsContext, region: &str) -> Result<()> {
+    let client = ctx.iam(region);
 
     // create role. This role will be used by EC2 instance
     // assume role policy document allows EC2 to assume this role and run the python script. Hence, it is hardcoded.
@@ -150,29 +184,29 @@ async fn create_ec2_role(region: &str) -> Result<(), IamError> {
         )
         .send()
         .await
-        .expect("Could not create role");
-
-    // loop while role is not created. This is needed because IAM is eventually consistent
-    while !is_role_exists(region)
-        .await
-        .expect("Could not check if role exists")
-    {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
+        .context("create the role of the instance")?;
 
-    Ok(())
+    // wait while role is not created. This is needed because IAM is eventually consistent
+    wait_until("the role to be created", IAM_CONSISTENCY_TIMEOUT, || {
+        is_role_exists(ctx, region)
+    })
+    .await
 }
 
 /// This function adds all required policies to the role associated with EC2 instance
 /// it reads the policy documents from folder src/policies one by one and adds them to the role inline
 /// it adjust each policy document to the region, account id, database name and table name as needed to make privillages
 /// absolutely MINIMAL.
-async fn add_policies_to_role(glue_table: &GlueTable) -> Result<(), IamError> {
+async fn add_policies_to_role(
+    ctx: &AwsContext,
+    glue_table: &GlueTable,
+    wheelhouse: Option<&str>,
+) -> Result<()> {
     let region = glue_table.database().region();
     // list all files in src/policies folder
-    let client = get_iam_client(region).await.expect("cannot get IAM client");
+    let client = ctx.iam(region);
 
-    for (policy_name, policy_document) in generate_policy_docs(glue_table) {
+    for (policy_name, policy_document) in generate_policy_docs(glue_table, wheelhouse) {
         let _response = client
             .put_role_policy()
             .role_name(PROJECT_NAME)
@@ -180,34 +214,34 @@ async fn add_policies_to_role(glue_table: &GlueTable) -> Result<(), IamError> {
             .policy_document(policy_document)
             .send()
             .await
-            .expect("Could not add policy to role");
+            .context(&format!("add the policy {} to the role", policy_name))?;
     }
 
     Ok(())
 }
 
-async fn add_role_to_instance_profile(region: &str) -> Result<(), IamError> {
-    let client = get_iam_client(region).await?;
-    create_ec2_role(region).await.unwrap();
+async fn add_role_to_instance_profile(ctx: &AwsContext, region: &str) -> Result<()> {
+    let client = ctx.iam(region);
+    create_ec2_role(ctx, region).await?;
     let _response = client
         .add_role_to_instance_profile()
         .instance_profile_name(PROJECT_NAME)
         .role_name(PROJECT_NAME)
         .send()
         .await
-        .expect("Could not add policy to instance profile");
+        .context("add the role to the instance profile")?;
     Ok(())
 }
 /// remove all roles from instance profile
-async fn remove_role_from_instance_profile(region: &str) -> Result<(), IamError> {
-    let client = get_iam_client(region).await?;
+async fn remove_role_from_instance_profile(ctx: &AwsContext, region: &str) -> Result<()> {
+    let client = ctx.iam(region);
     let _response = client
         .remove_role_from_instance_profile()
         .instance_profile_name(PROJECT_NAME)
         .role_name(PROJECT_NAME)
         .send()
         .await
-        .expect("Could not remove role from instance profile");
+        .context("remove the role from the instance profile")?;
     Ok(())
 }
 
@@ -227,56 +261,144 @@ fn get_all_policies() -> impl Iterator<Item = (String, String)> {
         });
     json_files
 }
+/// Checks if a policy is needed for the source of the table.
+/// Tables on S3 are read through Athena, JDBC tables through their Glue connection.
+fn is_policy_required(file_name: &str, glue_table: &GlueTable, wheelhouse: Option<&str>) -> bool {
+    match file_name {
+        "Wheelhouse" => wheelhouse.is_some(),
+        "S3Source" | "Athena" => !glue_table.is_jdbc(),
+        "Jdbc" => glue_table.connection().is_some(),
+        "GlueResourceLink" => glue_table.database().link_name().is_some(),
+        "JdbcSecret" => glue_table
+            .connection()
+            .map(|connection| connection.secret_id().is_some())
+            .unwrap_or(false),
+        _ => true,
+    }
+}
+
 /// given a policy name adjust for specific table
-fn generate_policy_docs(glue_table: &GlueTable) -> Vec<(String, String)> {
+fn generate_policy_docs(glue_table: &GlueTable, wheelhouse: Option<&str>) -> Vec<(String, String)> {
     let json_files = get_all_policies();
     let mut policy_docs: Vec<(String, String)> = Vec::new();
+    let region = glue_table.database().region();
+    let account_id = glue_table.database().account_id();
+    // shared databases, their tables and connections belong to the account owning the catalog
+    let catalog_id = glue_table.database().catalog_id();
     for (file_name, json_file_contents) in json_files {
+        if !is_policy_required(&file_name, glue_table, wheelhouse) {
+            continue;
+        }
+        let connection = glue_table.connection();
         let policy_document = json_file_contents
-            .replace("<your region>", &glue_table.database().region())
-            .replace("<your account>", &glue_table.database().account_id())
-            .replace("<your database>", &glue_table.database().name())
-            .replace("<your table>", &glue_table.name())
+            .replace("<your partition>", glue_table.partition())
+            .replace("<your region>", region)
+            .replace("<your account>", account_id)
+            .replace("<your catalog account>", catalog_id)
+            .replace("<your database>", glue_table.database().name())
+            .replace("<your query database>", glue_table.database().query_name())
+            .replace("<your table>", glue_table.name())
             .replace("<your project>", PROJECT_NAME)
             .replace("<your s3arn>", &glue_table.s3_arn())
             .replace(
-                "<your bucket>",
-                glue_table
-                    .s3_arn()
-                    .split(":::")
-                    .nth(1)
-                    .unwrap()
-                    .split("/")
-                    .nth(0)
-                    .unwrap(),
+                "\"<your source buckets>\"",
+                &to_json_list(glue_table.s3_bucket_arns()),
+            )
+            .replace(
+                "\"<your source prefixes>\"",
+                &to_json_list(
+                    glue_table
+                        .s3_source_arns()
+                        .iter()
+                        .map(|arn| format!("{}/*", arn)),
+                ),
+            )
+            .replace("<your output arn>", &glue_table.output_arn())
+            .replace(
+                "<your wheelhouse arn>",
+                &wheelhouse
+                    .map(|wheelhouse| to_s3_arn(wheelhouse, glue_table.partition()))
+                    .unwrap_or_default(),
+            )
+            .replace("<your output bucket>", &glue_table.output_bucket_arn())
+            .replace(
+                "<your connection>",
+                connection.map(|c| c.name().as_str()).unwrap_or_default(),
+            )
+            .replace(
+                "<your secret arn>",
+                &connection
+                    .and_then(|c| c.secret_arn(region, catalog_id))
+                    .unwrap_or_default(),
             );
         let policy_name = format!("{}{}", PROJECT_NAME, file_name);
         policy_docs.push((policy_name, policy_document));
     }
+    // IAM does not count whitespace towards the inline policy size limit
+    let policies_size: usize = policy_docs
+        .iter()
+        .map(|(_, policy_document)| {
+            policy_document
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .count()
+        })
+        .sum();
+    if policies_size > INLINE_POLICY_SIZE_LIMIT {
+        println!(
+            "{}",
+            format!(
+                "Warning: table {} has data in {} distinct S3 prefixes. The generated policies are {} characters long \
+                and exceed the IAM inline policy limit of {} characters, role creation is likely to fail.",
+                glue_table.name(),
+                glue_table.s3_source_arns().len(),
+                policies_size,
+                INLINE_POLICY_SIZE_LIMIT
+            )
+            .yellow()
+        );
+    }
     policy_docs
 }
 
+/// Formats the items as the body of a JSON list of strings
+fn to_json_list(items: impl IntoIterator<Item = String>) -> String {
+    items
+        .into_iter()
+        .map(|item| format!("\"{}\"", item))
+        .collect::<Vec<String>>()
+        .join(",\n                ")
+}
+
 ///removes all policies from role
 /// this is needed because we cannot delete role if it has policies attached
-async fn remove_all_policies_role(glue_table: &GlueTable) -> Result<(), IamError> {
-    let client = get_iam_client(glue_table.database().region()).await?;
+async fn remove_all_policies_role(ctx: &AwsContext, glue_table: &GlueTable) -> Result<()> {
+    let client = ctx.iam(glue_table.database().region());
 
-    let _response = &client
+    // the managed policy is not attached by this version of the CLI, only by older ones
+    match client
         .detach_role_policy()
         .role_name(PROJECT_NAME)
-        .policy_arn("arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore")
+        .policy_arn(format!(
+            "arn:{}:iam::aws:policy/AmazonSSMManagedInstanceCore",
+            glue_table.partition()
+        ))
         .send()
         .await
-        .expect("Could not remove policy from role");
+        .context("detach the managed policy from the role")
+    {
+        Ok(_) | Err(Error::NotFound(_)) => {}
+        Err(err) => return Err(err),
+    }
 
     let attached_policies = &client
         .list_role_policies()
         .role_name(PROJECT_NAME)
         .send()
         .await
-        .expect("Could not list attached policies")
+        .context("list the policies of the role")?
         .policy_names()
-        .unwrap()
+        .unwrap_or_default()
         .to_vec();
 
     for policy_name in attached_policies {
@@ -286,41 +408,74 @@ async fn remove_all_policies_role(glue_table: &GlueTable) -> Result<(), IamError
             .policy_name(policy_name)
             .send()
             .await
-            .expect("Could not remove policy from role");
+            .context(&format!("remove the policy {} from the role", policy_name))?;
     }
     Ok(())
 }
 
 /// check if role exists and instance profile exists
-pub async fn cleanup_aim(glue_table: &GlueTable) -> Result<(), IamError> {
+pub async fn cleanup_aim(ctx: &AwsContext, glue_table: &GlueTable) -> Result<()> {
     let region = glue_table.database().region();
-    if is_role_exists(region)
-        .await
-        .expect("Could not check if role exists")
-    {
-        remove_all_policies_role(glue_table)
-            .await
-            .expect("Could not remove policies from role");
-        if is_instance_profile_exists(region).await? {
-            remove_role_from_instance_profile(region).await.unwrap();
-            delete_instance_profile(region).await.unwrap();
+    if is_role_exists(ctx, region).await? {
+        remove_all_policies_role(ctx, glue_table).await?;
+        if is_instance_profile_exists(ctx, region).await? {
+            remove_role_from_instance_profile(ctx, region).await?;
+            delete_instance_profile(ctx, region).await?;
         }
-        delete_role(region).await.unwrap();
+        delete_role(ctx, region).await?;
     }
     Ok(())
 }
 pub async fn get_iam_instance_profile_specification(
+    ctx: &AwsContext,
     glue_table: &GlueTable,
-) -> Result<IamInstanceProfileSpecification, IamError> {
-    cleanup_aim(glue_table)
-        .await
-        .expect("Could not cleanup IAM");
+    wheelhouse: Option<&str>,
+) -> Result<IamInstanceProfileSpecification> {
+    cleanup_aim(ctx, glue_table).await?;
 
-    let instance_profile_arn = create_instance_profile(glue_table)
-        .await
-        .expect("Could not create instance profile");
+    let instance_profile_arn = create_instance_profile(ctx, glue_table, wheelhouse).await?;
 
     Ok(IamInstanceProfileSpecification::builder()
         .arn(instance_profile_arn)
         .build())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_glue_data::GlueDatabase;
+    use aws_sdk_glue::model::{StorageDescriptor, Table};
+
+    fn glue_table(region: &str) -> GlueTable {
+        let database = GlueDatabase::new(
+            region.to_string(),
+            "123456789012".to_string(),
+            "123456789012".to_string(),
+            "sales".to_string(),
+            None,
+        );
+        let table = Table::builder()
+            .name("orders")
+            .storage_descriptor(
+                StorageDescriptor::builder()
+                    .location("s3://bucket/orders/")
+                    .build(),
+            )
+            .build();
+        GlueTable::new(database, &table)
+    }
+
+    #[test]
+    fn policies_use_the_partition_of_the_region() {
+        let glue_table = glue_table("cn-north-1");
+        let policy_docs = generate_policy_docs(&glue_table, Some("s3://bucket/wheelhouse.tar.gz"));
+        assert!(policy_docs
+            .iter()
+            .any(|(name, _)| name.ends_with("Wheelhouse")));
+        for (name, policy_document) in policy_docs {
+            assert!(!policy_document.contains("<your"), "{}", name);
+            assert!(!policy_document.contains("arn:aws:"), "{}", name);
+            assert!(policy_document.contains("arn:aws-cn:"), "{}", name);
+        }
+    }
+}
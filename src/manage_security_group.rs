@@ -0,0 +1,131 @@
+//! # manage_security_group
+//!
+//! This module contains functions for managing the security group of a job.
+//! Every job that reads from S3 gets its own security group, without inbound rules and with
+//! HTTPS as the only outbound traffic, instead of the default security group of the VPC.
+//! JDBC tables keep the security groups of their Glue connection, which must reach the database.
+use crate::aws_context::AwsContext;
+use crate::error::{Error, Result, ResultExt};
+use crate::get_glue_data::GlueTable;
+use crate::retry::retry_while;
+use crate::PROJECT_NAME;
+use aws_sdk_ec2::model::{IpPermission, IpRange, ResourceType, Tag, TagSpecification};
+use chrono::Utc;
+use std::time::Duration;
+
+/// Time the network interface of a terminated instance takes to release the security group
+const SECURITY_GROUP_RELEASE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Rule matching all outbound traffic, or HTTPS only with `port`
+fn egress_rule(port: Option<i32>) -> IpPermission {
+    let rule = IpPermission::builder().ip_ranges(IpRange::builder().cidr_ip("0.0.0.0/0").build());
+    match port {
+        Some(port) => rule.ip_protocol("tcp").from_port(port).to_port(port),
+        None => rule.ip_protocol("-1"),
+    }
+    .build()
+}
+
+/// Creates the security group of the job in the VPC of the subnet and returns its id
+pub async fn create_job_security_group(
+    ctx: &AwsContext,
+    subnet_id: &str,
+    glue_table: &GlueTable,
+) -> Result<String> {
+    let client = ctx.ec2(glue_table.database().region());
+    let action = format!("read the VPC of subnet {}", subnet_id);
+    let vpc_id = client
+        .describe_subnets()
+        .subnet_ids(subnet_id)
+        .send()
+        .await
+        .context(&action)?
+        .subnets()
+        .and_then(|subnets| subnets.first())
+        .and_then(|subnet| subnet.vpc_id())
+        .map(|vpc_id| vpc_id.to_string())
+        .ok_or_else(|| Error::unexpected_response(&action, "VPC id"))?;
+
+    // group names are unique per VPC, the timestamp keeps jobs of the same table apart
+    let group_name = format!(
+        "{}-{}-{}",
+        PROJECT_NAME,
+        glue_table.name(),
+        Utc::now().timestamp()
+    );
+    let action = "create the security group of the job";
+    let group_id = client
+        .create_security_group()
+        .group_name(&group_name)
+        .description(format!(
+            "{} job for table {}, HTTPS egress only",
+            PROJECT_NAME,
+            glue_table.name()
+        ))
+        .vpc_id(vpc_id)
+        .tag_specifications(
+            TagSpecification::builder()
+                .resource_type(ResourceType::SecurityGroup)
+                .tags(Tag::builder().key("Name").value(PROJECT_NAME).build())
+                .build(),
+        )
+        .send()
+        .await
+        .context(action)?
+        .group_id()
+        .map(|group_id| group_id.to_string())
+        .ok_or_else(|| Error::unexpected_response(action, "security group id"))?;
+
+    // new groups allow all outbound traffic, replace the rule with HTTPS to reach the AWS APIs
+    let rules = async {
+        client
+            .authorize_security_group_egress()
+            .group_id(&group_id)
+            .ip_permissions(egress_rule(Some(443)))
+            .send()
+            .await
+            .context("allow HTTPS egress in the security group of the job")?;
+        client
+            .revoke_security_group_egress()
+            .group_id(&group_id)
+            .ip_permissions(egress_rule(None))
+            .send()
+            .await
+            .context("remove the default egress rule of the security group of the job")?;
+        Ok(())
+    };
+    if let Err(err) = rules.await {
+        let _ = delete_job_security_group(ctx, glue_table.database().region(), &group_id).await;
+        return Err(err);
+    }
+    Ok(group_id)
+}
+
+/// Deletes the security group of the job, once the terminated instance no longer uses it
+pub async fn delete_job_security_group(
+    ctx: &AwsContext,
+    region: &str,
+    group_id: &str,
+) -> Result<()> {
+    let client = ctx.ec2(region);
+    let action = format!("delete security group {}", group_id);
+    retry_while(
+        SECURITY_GROUP_RELEASE_TIMEOUT,
+        is_security_group_in_use,
+        || async {
+            client
+                .delete_security_group()
+                .group_id(group_id)
+                .send()
+                .await
+                .context(&action)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Checks if a deletion failed because a network interface still uses the security group
+fn is_security_group_in_use(err: &Error) -> bool {
+    matches!(err, Error::Aws { code, .. } if code == "DependencyViolation")
+}
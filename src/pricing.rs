@@ -0,0 +1,52 @@
+//! # pricing
+//! Rough cost of a job, shown on the summary screen before any resource is created.
+//! Prices are the on-demand list prices of us-east-1 in USD, other regions differ by a few percent.
+use aws_sdk_ec2::model::{InstanceType, VolumeType};
+
+/// Hours in a month, as used by AWS to turn monthly prices into hourly ones
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// On-demand price per hour of the instance type, `None` for types the CLI does not know
+pub fn instance_hourly_price(instance_type: &InstanceType) -> Option<f64> {
+    match instance_type {
+        InstanceType::C6iLarge => Some(0.085),
+        InstanceType::C6iXlarge => Some(0.17),
+        InstanceType::C6i2xlarge => Some(0.34),
+        InstanceType::C6i4xlarge => Some(0.68),
+        InstanceType::C6i8xlarge => Some(1.36),
+        InstanceType::M6iLarge => Some(0.096),
+        InstanceType::M6iXlarge => Some(0.192),
+        InstanceType::M6i2xlarge => Some(0.384),
+        InstanceType::M6i4xlarge => Some(0.768),
+        InstanceType::M6i8xlarge => Some(1.536),
+        InstanceType::M6i16xlarge => Some(3.072),
+        InstanceType::R6i16xlarge => Some(4.032),
+        InstanceType::C7gLarge => Some(0.0725),
+        InstanceType::C7gXlarge => Some(0.145),
+        InstanceType::C7g2xlarge => Some(0.289),
+        InstanceType::C7g4xlarge => Some(0.578),
+        InstanceType::C7g8xlarge => Some(1.156),
+        InstanceType::M6gLarge => Some(0.077),
+        InstanceType::M6gXlarge => Some(0.154),
+        InstanceType::M6g2xlarge => Some(0.308),
+        InstanceType::M6g4xlarge => Some(0.616),
+        InstanceType::M6g8xlarge => Some(1.232),
+        InstanceType::M6g16xlarge => Some(2.464),
+        InstanceType::R6g16xlarge => Some(3.2256),
+        _ => None,
+    }
+}
+
+/// Price per hour of a volume of the given type and size, without provisioned IOPS.
+/// Unknown types are priced as gp2, the most expensive general purpose type.
+pub fn volume_hourly_price(volume_type: &VolumeType, size_gib: i32) -> f64 {
+    let price_per_gib_month = match volume_type {
+        VolumeType::Gp3 => 0.08,
+        VolumeType::Io1 | VolumeType::Io2 => 0.125,
+        VolumeType::St1 => 0.045,
+        VolumeType::Sc1 => 0.015,
+        VolumeType::Standard => 0.05,
+        _ => 0.10,
+    };
+    f64::from(size_gib) * price_per_gib_month / HOURS_PER_MONTH
+}
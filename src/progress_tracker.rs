@@ -3,6 +3,7 @@
 //! to provide a simple progress bar for the user to see the progress of the data generation job.
 //! It grabs the last log line from CloudWatch and displays it to the user as the progress bar
 use crate::cw_logging::CWLogSender;
+use crate::error::Result;
 use crate::get_glue_data::GlueTable;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::process::Command;
@@ -13,10 +14,12 @@ use std::time::Duration;
 /// Running: The job is still running
 /// Completed: The job has completed successfully
 /// Failed: The job has failed
+/// Interrupted: EC2 reclaimed the spot instance before the job completed
 pub enum JobState {
     Running,
     Completed,
     Failed,
+    Interrupted,
 }
 /// This struct contains the progress bar and the CloudWatch logger to be used to update the progress bar
 /// The delay_secs is the number of seconds to wait between each update to the progress bar
@@ -50,10 +53,8 @@ impl ProgressTracker {
                     "▪▪▪▪▪",
                 ]),
         );
-        // clear the screen
-        Command::new("clear")
-            .status()
-            .expect("failed to clear screen");
+        // clear the screen, a terminal without `clear` only keeps the previous output
+        let _ = Command::new("clear").status();
         let tracker = ProgressTracker {
             logger,
             tracker: pb,
@@ -74,18 +75,14 @@ impl ProgressTracker {
     }
 
     /// Modify the state of the job
-    fn set_state(&mut self, state: JobState) -> () {
+    fn set_state(&mut self, state: JobState) {
         self.state = state;
     }
 
     /// Update the progress bar with the last log line from CloudWatch
-    pub async fn update_progress(&mut self) -> JobState {
+    pub async fn update_progress(&mut self) -> Result<JobState> {
         // get the last log line from CloudWatch
-        let last_log_line = self
-            .logger
-            .get_last_log_line()
-            .await
-            .expect("failed to get log line");
+        let last_log_line = self.logger.get_last_log_line().await?;
 
         // if the last log line is "Done" then the job is complete
         // This has to be coordinated with python code that runs the data generation job
@@ -93,12 +90,12 @@ impl ProgressTracker {
         if last_log_line.to_lowercase().eq("done") {
             self.set_state(JobState::Completed);
             self.finish();
-            return self.job_state();
+            return Ok(self.job_state());
         // Same as above but for "Failed"
         } else if last_log_line.to_lowercase().contains("failed") {
             self.set_state(JobState::Failed);
             self.failed();
-            return self.job_state();
+            return Ok(self.job_state());
         // Otherwise update the progress bar with the last log line
         } else {
             let message = format!(
@@ -110,16 +107,21 @@ impl ProgressTracker {
         // sleep is required here as we dont want to be constantly polling CloudWatch
         // state changes are infrequent and we dont want to be charged for excessive API calls
         std::thread::sleep(Duration::from_secs(self.delay_secs.into()));
-        self.job_state()
+        Ok(self.job_state())
     }
 
     /// Finish the progress bar if Done and clear the screen
-    pub fn finish(&self) -> () {
+    pub fn finish(&self) {
         self.tracker.finish_and_clear();
     }
 
     /// Finish the progress bar if Failed and clear the screen
-    pub fn failed(&self) -> () {
+    pub fn failed(&self) {
         self.tracker.finish_and_clear();
     }
+
+    /// Show a message until the next log line of the job arrives
+    pub fn set_message(&self, message: String) {
+        self.tracker.set_message(message);
+    }
 }
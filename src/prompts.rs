@@ -1,14 +1,25 @@
 //! # Prompts
 //!
 //! Implements the prompts for the CLI for the user to select the data they want to generate.
-//! In a linear workflow it asks the user to select the type of data they want to generate, then
-//! the database and table they want to generate data for.
+//! The wizard asks the user to select the type of data they want to generate, then the database
+//! and table they want to generate data for and where to run the job. Esc goes back a step, and
+//! nothing is created in the account before the user confirms the summary of the job.
+use crate::athena;
+use crate::aws_context::{AwsContext, AwsContextOptions};
+use crate::aws_credentials::RoleOptions;
+use crate::bundle::run_bundle;
+use crate::cli::{Architecture, Cli, Command as CliCommand, OnInterruption};
+use crate::config::{self, Config};
+use crate::error::{Error, Result};
 use crate::get_glue_data::{self, *};
 use crate::get_processing_job::{self, *};
+use crate::pricing;
+use crate::sizing::{self, SynthesisModel};
+use aws_sdk_ec2::model::InstanceType;
+use colored::*;
 use console::Term;
-use dialoguer::{theme::ColorfulTheme, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, Select};
 use std::convert::Into;
-use std::error::Error;
 use std::iter::Iterator;
 use std::process::Command;
 use std::str::FromStr;
@@ -24,11 +35,25 @@ enum WorkFlowType {
 
 /// Clear screen
 fn clear_screen() {
-    Command::new("clear").status().unwrap();
+    let _ = Command::new("clear").status();
+}
+
+/// Steps of the single table wizard, each holding the choices made before it
+enum Step {
+    Workflow,
+    Database,
+    Table(GlueDatabase),
+    Preview(GlueTable),
+    OutputLocation(GlueTable),
+    SampleSize(GlueTable),
+    Model(GlueTable),
+    InstanceType(GlueTable, SynthesisModel),
+    Subnet(GlueTable, JobOptions),
+    Summary(GlueTable, String, JobOptions),
 }
 
 /// Get the type of data to generate
-fn select_workflow_type() -> Result<WorkFlowType, Box<dyn Error>> {
+fn select_workflow_type() -> Result<Option<WorkFlowType>> {
     let items = WorkFlowType::iter()
         .map(|x| x.to_string())
         .collect::<Vec<_>>();
@@ -42,101 +67,687 @@ fn select_workflow_type() -> Result<WorkFlowType, Box<dyn Error>> {
 
     // Get the selection and convert to WorkFlowType
     match selection {
-        Ok(Some(index)) => Ok(WorkFlowType::from_str(items.get(index).unwrap()).unwrap()),
-        Ok(None) => Err("No selection made".into()),
+        Ok(Some(index)) => Ok(WorkFlowType::from_str(&items[index]).ok()),
+        Ok(None) => Ok(None),
         Err(err) => Err(err.into()),
     }
 }
 
 ///  Get the database to generate data for
-async fn select_database_name() -> Result<GlueDatabase, Box<dyn Error>> {
-    let items = get_glue_data::get_aws_glue_databases().await;
-    assert!(items.len() > 0, "No databases found in any region");
-    let selection = Select::with_theme(&ColorfulTheme::default())
+async fn select_database_name(ctx: &AwsContext) -> Result<Option<GlueDatabase>> {
+    let items = get_glue_data::get_aws_glue_databases(ctx).await?;
+    let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
         .items(&items.iter().map(|x| x.format_choice()).collect::<Vec<_>>())
         .default(0)
-        .with_prompt("Select source database for your data:")
+        .with_prompt("Select source database for your data (type to search):")
         .report(true)
         .interact_on_opt(&Term::stderr());
 
     match selection {
-        Ok(Some(index)) => Ok(items.get(index).unwrap().clone()),
-        Ok(None) => Err("No selection made".into()),
+        Ok(Some(index)) => Ok(Some(items[index].clone())),
+        Ok(None) => Ok(None),
         Err(err) => Err(err.into()),
     }
 }
 
 /// Get the table to generate data for
-async fn select_table_name(database: &GlueDatabase) -> Result<GlueTable, Box<dyn Error>> {
-    let items = get_one_glue_table(database).await;
-    assert!(items.len() > 0, "No tables found in database");
-    let selection = Select::with_theme(&ColorfulTheme::default())
+async fn select_table_name(ctx: &AwsContext, database: &GlueDatabase) -> Result<Option<GlueTable>> {
+    let items = get_one_glue_table(ctx, database).await?;
+    let selection = FuzzySelect::with_theme(&ColorfulTheme::default())
         .items(&items.iter().map(|x| x.format_choice()).collect::<Vec<_>>())
         .default(0)
-        .with_prompt("Select source database for your data:")
+        .with_prompt("Select source table for your data (type to search):")
         .report(true)
         .interact_on_opt(&Term::stderr());
 
     match selection {
-        Ok(Some(index)) => Ok(items.get(index).unwrap().clone()),
-        Ok(None) => Err("No selection made".into()),
+        Ok(Some(index)) => Ok(Some(items[index].clone())),
+        Ok(None) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Widest a column of the row preview gets before its values are cut
+const PREVIEW_COLUMN_WIDTH: usize = 24;
+
+/// Shows the schema of the table and, on request, its first rows.
+/// Returns whether the user continues with the table, false goes back to choose another one.
+async fn confirm_table(ctx: &AwsContext, table: &GlueTable) -> Result<bool> {
+    println!("\n{} {}", "Schema of".bold(), table.name().bold());
+    let columns = table.get_schema(ctx).await?;
+    let name_width = columns.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let type_width = columns.iter().map(|c| c.data_type.len()).max().unwrap_or(0);
+    for column in &columns {
+        let mut line = format!(
+            "  {:name_width$}  {:type_width$}",
+            column.name, column.data_type
+        );
+        if column.is_partition_key {
+            line.push_str("  (partition key)");
+        }
+        if let Some(comment) = &column.comment {
+            line.push_str(&format!("  {}", comment.dimmed()));
+        }
+        println!("{}", line.trim_end());
+    }
+    println!();
+
+    // Athena reads tables on S3, JDBC tables can only be previewed from inside their VPC
+    let preview = if table.is_jdbc() {
+        Some(false)
+    } else {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Preview the first rows of the table with Athena?")
+            .default(false)
+            .interact_on_opt(&Term::stderr())?
+    };
+    let Some(preview) = preview else {
+        return Ok(false);
+    };
+    if preview {
+        let rows: u64 = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Number of rows to preview:")
+            .default(10)
+            .interact_text_on(&Term::stderr())?;
+        match athena::preview_rows(ctx, table, rows).await {
+            Ok(rows) => print_rows(&rows),
+            // the preview is a convenience, the job does not need Athena
+            Err(err) => println!("{} {}", "Could not preview the table:".yellow(), err),
+        }
+    }
+
+    let choices = ["Continue with this table", "Choose another table"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(&choices)
+        .default(0)
+        .interact_on_opt(&Term::stderr());
+    match selection {
+        Ok(Some(index)) => Ok(index == 0),
+        Ok(None) => Ok(false),
         Err(err) => Err(err.into()),
     }
 }
 
+/// Prints the header and rows of the preview as aligned columns
+fn print_rows(rows: &[Vec<String>]) {
+    let truncate = |value: &str| {
+        if value.chars().count() > PREVIEW_COLUMN_WIDTH {
+            let cut = value
+                .chars()
+                .take(PREVIEW_COLUMN_WIDTH - 1)
+                .collect::<String>();
+            format!("{}…", cut)
+        } else {
+            value.to_string()
+        }
+    };
+    let rows = rows
+        .iter()
+        .map(|row| row.iter().map(|value| truncate(value)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut widths = vec![0; rows.iter().map(|row| row.len()).max().unwrap_or(0)];
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        if index == 0 {
+            println!("  {}", line.trim_end().bold());
+        } else {
+            println!("  {}", line.trim_end());
+        }
+    }
+    if rows.len() <= 1 {
+        println!("  {}", "The table has no rows".dimmed());
+    }
+    println!();
+}
+
+/// Get the S3 location to write the synthetic copy of a JDBC table to.
+/// Text prompts do not read Esc, an empty answer goes back a step.
+fn select_output_location(table: &GlueTable) -> Result<Option<String>> {
+    let output_location: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "S3 prefix to write the synthetic copy of {} to (empty to go back):",
+            table.name()
+        ))
+        .allow_empty(true)
+        .validate_with(|input: &String| -> std::result::Result<(), &str> {
+            if input.is_empty()
+                || input.to_lowercase().starts_with("s3://") && input.len() > "s3://".len()
+            {
+                Ok(())
+            } else {
+                Err("The location must be an S3 prefix, for example s3://my-bucket/synthetic/")
+            }
+        })
+        .interact_text_on(&Term::stderr())?;
+    Ok(Some(output_location).filter(|location| !location.is_empty()))
+}
+
+/// Get the number of rows to read from the source, 0 reads the whole table
+fn select_sample_size() -> Result<Option<u64>> {
+    let sample_size: u64 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Number of rows to read from the source (0 reads all rows):")
+        .default(0)
+        .interact_text_on(&Term::stderr())?;
+    Ok(Some(sample_size).filter(|rows| *rows > 0))
+}
+
 /// Get valid subnet to run the job in
-async fn select_vpc_id(my_region: &str) -> Result<ValidSubnet, Box<dyn Error>> {
-    let items = get_processing_job::get_subnet_list(my_region)
-        .await
-        .expect("Failed to get subnet list");
+/// Returns the subnets in availability zones that offer the instance type
+async fn get_offered_subnets(
+    ctx: &AwsContext,
+    my_region: &str,
+    instance_type: &InstanceType,
+) -> Result<Vec<ValidSubnet>> {
+    let zones = get_processing_job::get_instance_type_zones(ctx, my_region, instance_type).await?;
+    let (items, hidden): (Vec<_>, Vec<_>) = get_processing_job::get_subnet_list(ctx, my_region)
+        .await?
+        .into_iter()
+        .partition(|subnet| zones.contains(subnet.get_availability_zone()));
+    if !hidden.is_empty() {
+        println!(
+            "{}",
+            format!(
+                "{} subnets are hidden, their availability zone does not offer {}.",
+                hidden.len(),
+                instance_type.as_str()
+            )
+            .dimmed()
+        );
+    }
+    Ok(items)
+}
+
+/// Get the subnet to run the job in, the subnet last chosen in the region comes first
+fn select_subnet(my_region: &str, items: &[ValidSubnet]) -> Result<Option<ValidSubnet>> {
+    let last_subnet = config::last_subnet(my_region);
     let display_items = items
         .iter()
         .map(|x| x.format_for_display())
         .collect::<Vec<_>>();
     let selection = Select::with_theme(&ColorfulTheme::default())
         .items(&display_items)
-        .default(0)
+        .default(
+            items
+                .iter()
+                .position(|subnet| Some(subnet.get_subnet()) == last_subnet.as_ref())
+                .unwrap_or(0),
+        )
         .with_prompt("Select Subnet to run your job in:")
         .report(true)
         .interact_on_opt(&Term::stderr());
 
     match selection {
-        Ok(Some(index)) => Ok(items.get(index).unwrap().clone()),
-        Ok(None) => Err("No selection made".into()),
+        Ok(Some(index)) => Ok(Some(items[index].clone())),
+        Ok(None) => Ok(None),
         Err(err) => Err(err.into()),
     }
 }
+/// Get the model to synthesize the table with
+fn select_model() -> Result<Option<SynthesisModel>> {
+    let items = SynthesisModel::iter().collect::<Vec<_>>();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(
+            &items
+                .iter()
+                .map(|model| format!("{} ({})", model, model.description()))
+                .collect::<Vec<_>>(),
+        )
+        .default(0)
+        .with_prompt("Select the model to generate the data with:")
+        .report(true)
+        .interact_on_opt(&Term::stderr());
+
+    match selection {
+        Ok(Some(index)) => Ok(Some(items[index])),
+        Ok(None) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Get the instance type to run the job on, the recommendation for the table comes first
+fn select_instance_type(
+    table: &GlueTable,
+    model: SynthesisModel,
+    architecture: Architecture,
+) -> Result<Option<InstanceType>> {
+    let offers = sizing::instance_offers(architecture);
+    let recommended = sizing::recommend_instance_type(table, model, architecture);
+    match sizing::required_memory_gib(table, model) {
+        Some(memory_gib) => println!(
+            "{}",
+            format!(
+                "{} needs about {} GiB of memory for this table.",
+                model, memory_gib
+            )
+            .dimmed()
+        ),
+        None => println!(
+            "{}",
+            "The table has no crawler statistics, the size of the job is a guess.".dimmed()
+        ),
+    }
+    let items = offers
+        .iter()
+        .map(|offer| {
+            let mut item = format!(
+                "{:14}{:3} vCPUs {:4} GiB",
+                offer.instance_type.as_str(),
+                offer.vcpus,
+                offer.memory_gib
+            );
+            if let Some(price) = pricing::instance_hourly_price(&offer.instance_type) {
+                item.push_str(&format!("  ${:.2}/hour", price));
+            }
+            if offer.instance_type == recommended {
+                item.push_str("  (recommended)");
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(&items)
+        .default(
+            offers
+                .iter()
+                .position(|offer| offer.instance_type == recommended)
+                .unwrap_or(0),
+        )
+        .with_prompt("Select the instance type to run the job on:")
+        .report(true)
+        .interact_on_opt(&Term::stderr());
+
+    match selection {
+        Ok(Some(index)) => Ok(Some(offers[index].instance_type.clone())),
+        Ok(None) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Settings of the job from the choices of the wizard and the command line options
+fn job_options(
+    cli: &Cli,
+    wheelhouse: &Option<Wheelhouse>,
+    table: &GlueTable,
+    model: SynthesisModel,
+    instance_type: InstanceType,
+) -> JobOptions {
+    JobOptions {
+        architecture: cli.arch,
+        instance_type,
+        model,
+        spot: cli.spot,
+        on_interruption: cli.on_interruption,
+        volume_size: cli
+            .volume_size
+            .unwrap_or_else(|| sizing::recommend_volume_size(table, &cli.volume_type)),
+        volume_type: cli.volume_type.clone(),
+        encrypted: !cli.unencrypted,
+        kms_key_id: cli.kms_key_id.clone(),
+        ami: cli.ami.clone(),
+        wheelhouse: wheelhouse.clone(),
+    }
+}
+
+/// Shows what the job creates and what it costs, and asks for an explicit confirmation.
+/// Returns `None` when Esc goes back a step.
+async fn confirm_summary(
+    ctx: &AwsContext,
+    table: &GlueTable,
+    subnet_id: &str,
+    options: &JobOptions,
+) -> Result<Option<bool>> {
+    let hourly_cost = pricing::instance_hourly_price(&options.instance_type).map(|price| {
+        price + pricing::volume_hourly_price(&options.volume_type, options.volume_size)
+    });
+    let volume = match (options.encrypted, &options.kms_key_id) {
+        (false, _) => "unencrypted",
+        (true, Some(_)) => "encrypted with KMS key",
+        (true, None) => "encrypted with the default EBS key",
+    };
+    let subnet = match table.connection() {
+        Some(connection) => format!("{} (from Glue connection {})", subnet_id, connection.name()),
+        None => subnet_id.to_string(),
+    };
+    let output_table = match table.connection() {
+        Some(_) => format!(
+            "{}.{} at {}",
+            table.database().query_name(),
+            table.output_table_name(),
+            table.output_location()
+        ),
+        None => format!(
+            "{}.{}",
+            table.database().query_name(),
+            table.output_table_name()
+        ),
+    };
+    let rows = [
+        ("Account", ctx.account_id().await?),
+        ("Region", table.database().region().to_string()),
+        (
+            "Source table",
+            format!("{}.{}", table.database().name(), table.name()),
+        ),
+        ("Subnet", subnet),
+        ("Model", options.model.to_string()),
+        ("Instance type", options.instance_type.as_str().to_string()),
+        (
+            "Image",
+            options.ami.clone().unwrap_or_else(|| {
+                format!("latest Amazon Linux 2023 {}", options.architecture.as_str())
+            }),
+        ),
+        (
+            "Root volume",
+            format!(
+                "{} GiB {} {} {}",
+                options.volume_size,
+                options.volume_type.as_str(),
+                volume,
+                options.kms_key_id.as_deref().unwrap_or_default()
+            )
+            .trim_end()
+            .to_string(),
+        ),
+        (
+            "Packages",
+            match &options.wheelhouse {
+                Some(Wheelhouse {
+                    location,
+                    sha256: Some(_),
+                }) => format!("from the wheelhouse {}, hash verified", location),
+                Some(Wheelhouse {
+                    location,
+                    sha256: None,
+                }) => format!("from the wheelhouse {}", location),
+                None => "pinned versions from PyPI".to_string(),
+            },
+        ),
+        (
+            "Capacity",
+            match (options.spot, options.on_interruption) {
+                (false, _) => "on-demand".to_string(),
+                (true, OnInterruption::Relaunch) => {
+                    "spot, relaunched on-demand if interrupted".to_string()
+                }
+                (true, OnInterruption::Stop) => "spot, stopped if interrupted".to_string(),
+            },
+        ),
+        (
+            "Estimated cost",
+            match (hourly_cost, options.spot) {
+                (Some(cost), false) => format!(
+                    "about ${:.2} per hour while the job runs (on-demand prices of us-east-1)",
+                    cost
+                ),
+                (Some(cost), true) => format!(
+                    "at most ${:.2} per hour while the job runs, spot prices are usually far lower",
+                    cost
+                ),
+                (None, _) => "unknown".to_string(),
+            },
+        ),
+        ("Output table", output_table),
+    ];
+
+    println!("\n{}", "Summary of the job".bold());
+    for (label, value) in rows {
+        println!("  {:16}{}", label, value);
+    }
+    println!(
+        "\n{}",
+        "Confirming launches an EC2 instance and creates an IAM role and a CloudWatch log stream for it."
+            .dimmed()
+    );
+    let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Create these resources and start the job?")
+        .default(false)
+        .interact_on_opt(&Term::stderr())?;
+    Ok(confirmed)
+}
+
+/// Get the current code of the MFA device
+fn select_mfa_token(mfa_serial: &str) -> Result<String> {
+    let mfa_token: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("MFA code for {}:", mfa_serial))
+        .interact_text_on(&Term::stderr())?;
+    Ok(mfa_token)
+}
+
 /// Run the workflow for the user to select the data they want to generate
-pub async fn run_workflow() -> Result<(), Box<dyn Error>> {
+pub async fn run_workflow(cli: Cli) -> Result<()> {
+    // all AWS clients are built from the assumed role credentials if a role is given
+    let role = match &cli.role_arn {
+        Some(role_arn) => {
+            let mfa_token = match (&cli.mfa_serial, cli.mfa_token.clone()) {
+                (Some(mfa_serial), None) => Some(select_mfa_token(mfa_serial)?),
+                (_, mfa_token) => mfa_token,
+            };
+            Some(RoleOptions {
+                role_arn: role_arn.clone(),
+                external_id: cli.external_id.clone(),
+                mfa_serial: cli.mfa_serial.clone(),
+                mfa_token,
+            })
+        }
+        None => None,
+    };
+    // command line options take precedence over the config file
+    if let Some(volume_size) = cli.volume_size {
+        sizing::check_volume_size(&cli.volume_type, volume_size)?;
+    }
+    let config = Config::load()?;
+    // the hash in the config file belongs to the wheelhouse in the config file
+    let wheelhouse = match &cli.wheelhouse {
+        Some(location) => Some(Wheelhouse {
+            location: location.clone(),
+            sha256: cli.wheelhouse_sha256.clone(),
+        }),
+        None => config.wheelhouse(cli.arch).map(|location| Wheelhouse {
+            location,
+            sha256: config.wheelhouse_sha256(cli.arch),
+        }),
+    };
+    let regions = cli
+        .regions
+        .clone()
+        .or_else(|| cli.region.clone().map(|region| vec![region]))
+        .or_else(|| config.regions());
+    let ctx = AwsContext::new(AwsContextOptions {
+        profile: cli.profile.clone().or_else(|| config.profile()),
+        region: cli.region.clone(),
+        default_region: config.region(),
+        regions,
+        role,
+        simulate_instance: cli.simulate_instance,
+        refresh: cli.refresh,
+        endpoint_urls: config.endpoint_urls(),
+    })
+    .await?;
+    if let Some(CliCommand::Bundle(args)) = &cli.command {
+        return run_bundle(&ctx, args, cli.arch).await;
+    }
     clear_screen();
-    match select_workflow_type().unwrap() {
-        WorkFlowType::SingleTable => {
+    println!("{}", "Press Esc to go back a step.".dimmed());
+
+    let mut step = Step::Workflow;
+    let (table, subnet_id, options) = loop {
+        step = match step {
+            Step::Workflow => match select_workflow_type()? {
+                Some(WorkFlowType::SingleTable) => Step::Database,
+                Some(WorkFlowType::MultiTable) => {
+                    println!("Multi Table");
+                    return Ok(());
+                }
+                Some(WorkFlowType::TimeSeries) => {
+                    println!("Time Series");
+                    return Ok(());
+                }
+                None => return Err(Error::Cancelled),
+            },
             // Get the database and table to generate data for
-            let database = select_database_name()
-                .await
-                .expect("Failed to get database name");
-
-            // Get the table to generate data for
-            let table = select_table_name(&database)
-                .await
-                .expect("Failed to get table name");
-
-            let valid_subnet = select_vpc_id(database.region())
-                .await
-                .expect("Failed to get subnet id");
-
-            run_sythetic_data_job(&valid_subnet.get_subnet(), &table)
-                .await
-                .expect("Failed to create EC2 instance");
-            Ok(())
+            Step::Database => match select_database_name(&ctx).await? {
+                Some(database) => Step::Table(database),
+                None => Step::Workflow,
+            },
+            Step::Table(database) => match select_table_name(&ctx, &database).await? {
+                Some(table) => Step::Preview(table),
+                None => Step::Database,
+            },
+            Step::Preview(mut table) => {
+                if !confirm_table(&ctx, &table).await? {
+                    Step::Table(table.database().clone())
+                } else if table.is_jdbc() {
+                    // JDBC tables are read through the network settings of their Glue connection
+                    table.set_connection(&ctx).await?;
+                    Step::OutputLocation(table)
+                } else {
+                    // partitions can live outside of the table location, collect them for the IAM policies
+                    table.set_partition_locations(&ctx).await?;
+                    Step::Model(table)
+                }
+            }
+            Step::OutputLocation(mut table) => match select_output_location(&table)? {
+                Some(output_location) => {
+                    table.set_output_location(output_location);
+                    Step::SampleSize(table)
+                }
+                None => Step::Table(table.database().clone()),
+            },
+            Step::SampleSize(mut table) => {
+                table.set_sample_size(select_sample_size()?);
+                Step::Model(table)
+            }
+            Step::Model(table) => match select_model()? {
+                // an instance type given on the command line is not asked for
+                Some(model) => match &cli.instance_type {
+                    Some(instance_type) => {
+                        check_instance_architecture(
+                            &ctx,
+                            table.database().region(),
+                            instance_type,
+                            cli.arch,
+                        )
+                        .await?;
+                        let options =
+                            job_options(&cli, &wheelhouse, &table, model, instance_type.clone());
+                        Step::Subnet(table, options)
+                    }
+                    None => Step::InstanceType(table, model),
+                },
+                None if table.is_jdbc() => Step::SampleSize(table),
+                None => Step::Table(table.database().clone()),
+            },
+            Step::InstanceType(table, model) => {
+                match select_instance_type(&table, model, cli.arch)? {
+                    Some(instance_type) => {
+                        let options = job_options(&cli, &wheelhouse, &table, model, instance_type);
+                        Step::Subnet(table, options)
+                    }
+                    None => Step::Model(table),
+                }
+            }
+            // the subnet is chosen after the instance type, not every availability zone offers it
+            Step::Subnet(table, options) => {
+                let region = table.database().region().to_string();
+                let instance_type = options.instance_type.as_str().to_string();
+                // an instance type given on the command line cannot be chosen again in the wizard
+                let unavailable = |message: String| match cli.instance_type {
+                    Some(_) => Err(Error::Config(format!(
+                        "{}, pass another --instance-type",
+                        message
+                    ))),
+                    None => {
+                        println!(
+                            "{}",
+                            format!("{}, choose another instance type.", message).yellow()
+                        );
+                        Ok(Step::InstanceType(table.clone(), options.model))
+                    }
+                };
+                match table.connection() {
+                    // JDBC tables run in the subnet of their Glue connection
+                    Some(connection) => {
+                        let subnet_id = connection.subnet_id().to_string();
+                        let zone = get_subnet_zone(&ctx, &region, &subnet_id).await?;
+                        let zones =
+                            get_instance_type_zones(&ctx, &region, &options.instance_type).await?;
+                        if zones.contains(&zone) {
+                            Step::Summary(table, subnet_id, options)
+                        } else {
+                            unavailable(format!(
+                                "{} is not offered in {}, the availability zone of the subnet {} of the Glue connection",
+                                instance_type, zone, subnet_id
+                            ))?
+                        }
+                    }
+                    None => {
+                        let subnets =
+                            get_offered_subnets(&ctx, &region, &options.instance_type).await?;
+                        if subnets.is_empty() {
+                            unavailable(format!(
+                                "None of the subnets is in an availability zone offering {}",
+                                instance_type
+                            ))?
+                        } else {
+                            match select_subnet(&region, &subnets)? {
+                                // without internet access the worker cannot install its packages from PyPI
+                                Some(subnet)
+                                    if subnet.get_egress() == SubnetEgress::VpcEndpoints
+                                        && wheelhouse.is_none() =>
+                                {
+                                    println!(
+                                        "{}",
+                                        format!(
+                                            "Subnet {} reaches AWS through VPC endpoints only. Pass --wheelhouse or set \
+                                            wheelhouse in the config file to use it, or choose another subnet.",
+                                            subnet.get_subnet()
+                                        )
+                                        .yellow()
+                                    );
+                                    Step::Subnet(table, options)
+                                }
+                                Some(subnet) => {
+                                    let subnet_id = subnet.get_subnet().to_string();
+                                    Step::Summary(table, subnet_id, options)
+                                }
+                                None if cli.instance_type.is_some() => Step::Model(table),
+                                None => Step::InstanceType(table, options.model),
+                            }
+                        }
+                    }
+                }
+            }
+            Step::Summary(table, subnet_id, options) => {
+                match confirm_summary(&ctx, &table, &subnet_id, &options).await? {
+                    Some(true) => break (table, subnet_id, options),
+                    Some(false) => return Err(Error::Cancelled),
+                    None if !table.is_jdbc() => Step::Subnet(table, options),
+                    None if cli.instance_type.is_some() => Step::Model(table),
+                    None => Step::InstanceType(table, options.model),
+                }
+            }
         }
-        WorkFlowType::MultiTable => {
-            println!("Multi Table");
-            Ok(())
-        }
-        WorkFlowType::TimeSeries => {
-            println!("Time Series");
-            Ok(())
+    };
+
+    // the subnet of a confirmed job is offered first next time, JDBC tables do not choose one
+    if !table.is_jdbc() {
+        if let Err(err) = config::set_last_subnet(table.database().region(), &subnet_id) {
+            eprintln!(
+                "{}",
+                format!("Could not remember subnet {}: {}", subnet_id, err).yellow()
+            );
         }
     }
+    run_sythetic_data_job(&ctx, &subnet_id, &table, &options).await
 }
@@ -0,0 +1,168 @@
+//! # retry
+//! Shared retry policy of the CLI.
+//! AWS calls are retried by the SDK with jittered exponential backoff, which also covers throttling.
+//! Waits for eventually consistent changes (IAM roles, instance profiles, log streams) poll with
+//! the same backoff and give up after a time limit instead of looping forever.
+use crate::error::{Error, Result};
+use aws_smithy_types::retry::RetryConfig;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Attempts of every AWS call, including the first one
+const MAX_ATTEMPTS: u32 = 8;
+/// Backoff before the first retry, doubled on every attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound of the backoff between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(20);
+
+/// Retry configuration of the AWS clients
+pub fn retry_config() -> RetryConfig {
+    RetryConfig::standard()
+        .with_max_attempts(MAX_ATTEMPTS)
+        .with_initial_backoff(INITIAL_BACKOFF)
+}
+
+/// Returns the delay before the given retry, between half and all of the exponential backoff
+fn backoff(attempt: u32) -> Duration {
+    let backoff = INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+    backoff / 2 + (backoff / 2).mul_f64(fastrand::f64())
+}
+
+/// Polls `check` until it returns true. Fails with a timeout error naming `what` after `timeout`.
+pub async fn wait_until<F, Fut>(what: &str, timeout: Duration, mut check: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 0;
+    while !check().await? {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout(format!(
+                "Timed out after {} seconds waiting for {}",
+                timeout.as_secs(),
+                what
+            )));
+        }
+        tokio::time::sleep(backoff(attempt)).await;
+        attempt += 1;
+    }
+    Ok(())
+}
+
+/// Runs `operation` again while it fails with an error accepted by `is_retryable`,
+/// for errors the SDK does not know to be transient. Gives up after `timeout`.
+pub async fn retry_while<T, F, Fut>(
+    timeout: Duration,
+    is_retryable: impl Fn(&Error) -> bool,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Err(err) if is_retryable(&err) && Instant::now() < deadline => {
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn backoff_stays_between_half_and_all_of_the_exponential_delay() {
+        for _ in 0..100 {
+            let first = backoff(0);
+            assert!(first >= INITIAL_BACKOFF / 2 && first <= INITIAL_BACKOFF);
+            let third = backoff(2);
+            assert!(third >= INITIAL_BACKOFF * 2 && third <= INITIAL_BACKOFF * 4);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        for attempt in [6, 20, 40, u32::MAX] {
+            let delay = backoff(attempt);
+            assert!(delay >= MAX_BACKOFF / 2 && delay <= MAX_BACKOFF);
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_until_polls_until_the_check_passes() {
+        let checks = Cell::new(0);
+        wait_until("the test", Duration::from_secs(10), || async {
+            checks.set(checks.get() + 1);
+            Ok(checks.get() == 2)
+        })
+        .await
+        .unwrap();
+        assert_eq!(checks.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn wait_until_times_out() {
+        let result = wait_until("the test", Duration::ZERO, || async { Ok(false) }).await;
+        match result {
+            Err(Error::Timeout(message)) => assert!(message.contains("the test")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_until_returns_the_error_of_the_check() {
+        let result = wait_until("the test", Duration::from_secs(10), || async {
+            Err(Error::Cancelled)
+        })
+        .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn retry_while_retries_accepted_errors() {
+        let attempts = Cell::new(0);
+        let result = retry_while(
+            Duration::from_secs(10),
+            |err| matches!(err, Error::Throttled { .. }),
+            || async {
+                attempts.set(attempts.get() + 1);
+                match attempts.get() {
+                    1 => Err(Error::Throttled {
+                        action: "test".to_string(),
+                        message: "slow down".to_string(),
+                    }),
+                    attempt => Ok(attempt),
+                }
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_while_returns_other_errors_at_once() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = retry_while(
+            Duration::from_secs(10),
+            |err| matches!(err, Error::Throttled { .. }),
+            || async {
+                attempts.set(attempts.get() + 1);
+                Err(Error::Cancelled)
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -1,7 +1,9 @@
 #!/bin/bash 
 set -x
 
-region=$(curl http://169.254.169.254/latest/meta-data/placement/availability-zone | sed 's/.$//')
+# the instance only accepts IMDSv2, every metadata request needs a session token
+imds_token=$(curl -s -X PUT http://169.254.169.254/latest/api/token -H "X-aws-ec2-metadata-token-ttl-seconds: 21600")
+region=$(curl -s -H "X-aws-ec2-metadata-token: $imds_token" http://169.254.169.254/latest/meta-data/placement/region)
 
 
 
@@ -11,16 +13,32 @@ function send_cw_logs {
 
 
 
-send_cw_logs "Installing Python 3.8 ..."
+send_cw_logs "Installing Python 3.11 ..."
 
-sudo amazon-linux-extras install -y python3.8
-version_python=$(python3.8 --version)
+sudo dnf install -y python3.11 python3.11-pip
+version_python=$(python3.11 --version)
 
-send_cw_logs "Python 3.8 Installed ..."
+send_cw_logs "Python 3.11 Installed ..."
 
 send_cw_logs "Installing required packages ..."
-pip3.8  install sdv
-pip3.8 install awswrangler
+# subnets without internet access install the packages from a wheelhouse on S3 instead of PyPI
+wheelhouse="<your wheelhouse>"
+wheelhouse_sha256="<your wheelhouse sha256>"
+if [ -n "$wheelhouse" ]; then
+    aws s3 cp "$wheelhouse" wheelhouse.tar.gz --region $region
+    if [ -n "$wheelhouse_sha256" ] && ! echo "$wheelhouse_sha256  wheelhouse.tar.gz" | sha256sum -c -; then
+        send_cw_logs "Script failed: the wheelhouse does not match its SHA-256 hash"
+        exit 1
+    fi
+    mkdir -p wheelhouse && tar -xzf wheelhouse.tar.gz -C wheelhouse
+    python3.11 -m pip install --no-index --find-links wheelhouse -r wheelhouse/requirements.txt
+else
+    cat > requirements.txt << REQUIREMENTS
+<your requirements>
+REQUIREMENTS
+    # arm64 workers get wheels where PyPI has them, the instance has no compilers for the rest
+    python3.11 -m pip install --prefer-binary -r requirements.txt
+fi
 send_cw_logs "Required packages installed ..."
 
 send_cw_logs "Starting Data Creation Script ..."
@@ -28,7 +46,10 @@ cat > script.py << EOF
 <your python script>
 EOF
 # Run the script and redirect the stderr to a variable
-error_message=$(python3.8 script.py $region <your database> <your table> <your project> <your table> 2>&1)
+error_message=$(python3.11 script.py $region <your database> <your table> <your project> <your table> \
+    --table-format <your table format> --output-location "<your output location>" \
+    --connection "<your connection>" --table-location "<your table location>" --sample-size <your sample size> \
+    --catalog-id <your catalog id> --query-database <your query database> --model <your model> 2>&1)
 
 # check the exit status and store it in a variable
 result=$?
@@ -1,9 +1,10 @@
-from sdv import Metadata
 import pandas as pd
 import awswrangler as wr
-from sdv.tabular import CTGAN
+from sdv.metadata import SingleTableMetadata
+from sdv.single_table import (CopulaGANSynthesizer, CTGANSynthesizer,
+                              GaussianCopulaSynthesizer, TVAESynthesizer)
 import datetime
-import sys
+import argparse
 import boto3
 import datetime
 from functools import partial
@@ -27,10 +28,64 @@ def send_logs_cloudwatch(client, log_group_name, log_stream_name, message):
     )
 
 
-# Load the data for a single table
-def get_table(table_name, database_name):
+# Hudi adds bookkeeping columns to every record, they are not part of the data and must not be synthesized
+HUDI_META_COLUMNS = ["_hoodie_commit_time", "_hoodie_commit_seqno",
+                     "_hoodie_record_key", "_hoodie_partition_path", "_hoodie_file_name"]
+
+
+# Iceberg and Delta Lake tables can only be read by Athena engine version 3
+def check_athena_engine(table_format):
+    if table_format not in ("Iceberg", "DeltaLake"):
+        return
+    work_group = wr.athena.get_work_group("primary")
+    engine_version = work_group["WorkGroup"]["Configuration"]["EngineVersion"]["EffectiveEngineVersion"]
+    if "3" not in engine_version:
+        raise RuntimeError(
+            f"{table_format} tables require Athena engine version 3, workgroup primary uses {engine_version}")
+
+
+# Load the data for a single table from S3 through Athena
+def get_table(table_name, database_name, table_format, sample_size):
+    check_athena_engine(table_format)
+    query = f'SELECT * FROM "{table_name}"'
+    if sample_size > 0:
+        query += f" LIMIT {sample_size}"
     data = wr.athena.read_sql_query(
-        f"SELECT * FROM {table_name}", database=database_name, ctas_approach=False, s3_output=get_table_location(table_name, database_name).rstrip("/") + "_athena")
+        query, database=database_name, ctas_approach=False, s3_output=get_table_location(table_name, database_name).rstrip("/") + "_athena")
+    if table_format == "Hudi":
+        data = data.drop(columns=[column for column in HUDI_META_COLUMNS if column in data.columns])
+    return convert_types(data)
+
+
+# Load the data for a single table from a database through its Glue connection
+# awswrangler reads the credentials from the connection or its Secrets Manager secret
+# connections of tables reached through a resource link live in the catalog owning the table
+def get_jdbc_table(connection_name, table_location, sample_size, catalog_id):
+    catalog = {"CatalogId": catalog_id} if catalog_id else {}
+    connection = boto3.client("glue").get_connection(
+        Name=connection_name, HidePassword=True, **catalog)["Connection"]
+    # jdbc:<engine>://... engine matches the awswrangler module name (mysql, postgresql, redshift, oracle)
+    # the CLI rejects SQL Server connections, awswrangler reads them through an ODBC driver the worker lacks
+    engine = connection["ConnectionProperties"]["JDBC_CONNECTION_URL"].split(":")[1]
+    engine_module = getattr(wr, engine)
+    # crawlers store the location as <database>.<schema>.<table> or <database>.<table>
+    # the database is already part of the connection url
+    source = table_location.split(".", 1)[1]
+    if sample_size > 0 and engine == "oracle":
+        query = f"SELECT * FROM {source} FETCH FIRST {sample_size} ROWS ONLY"
+    elif sample_size > 0:
+        query = f"SELECT * FROM {source} LIMIT {sample_size}"
+    else:
+        query = f"SELECT * FROM {source}"
+    con = engine_module.connect(connection=connection_name, catalog_id=catalog_id)
+    try:
+        data = engine_module.read_sql_query(query, con=con)
+    finally:
+        con.close()
+    return convert_types(data)
+
+
+def convert_types(data):
     # lambda function to convert to SDV compatible types
     # SDV does not support Int64,float64 and string types.  int64 is converted to int and float64 is converted to float
     # One exception is if int64 has null values then it is converted to float. int64 with null values is not supported by pandas.
@@ -57,23 +112,31 @@ def get_table(table_name, database_name):
 def get_table_location(table_name, database_name):
     return wr.catalog.get_table_location(database=database_name, table=table_name)
 
+# models the synthetic data can be generated with, chosen in the CLI
+MODELS = {
+    "CTGAN": CTGANSynthesizer,
+    "GaussianCopula": GaussianCopulaSynthesizer,
+    "CopulaGAN": CopulaGANSynthesizer,
+    "TVAE": TVAESynthesizer,
+}
+
 # generate sythetic data of the same size as the original table
 
 
-def generate_sythetic_data(data, send_status):
-    send_status("Training model...")
-    model = CTGAN()
+def generate_sythetic_data(data, model_name, send_status):
+    send_status("Training " + model_name + " model...")
+    # SDV 1.x synthesizers need the column types, detected from the data
+    metadata = SingleTableMetadata()
+    metadata.detect_from_dataframe(data)
+    model = MODELS[model_name](metadata)
     model.fit(data)
     send_status("Generating sythetic data using model...")
-    return model.sample(data.shape[0])
+    return model.sample(num_rows=data.shape[0])
 
 # save sythetic data to s3
 
 
-def save_sythetic_data(synthetic_data, table_name, database_name):
-    table_location = get_table_location(table_name, database_name)
-    # add sythetic word to location string
-    synthetic_location = table_location.rstrip("/") + "_synthetic"
+def save_sythetic_data(synthetic_data, table_name, database_name, synthetic_location, catalog_id):
     # add sythetic work to the table name
     synthetic_table_name = table_name + "_synthetic"
     res = wr.s3.to_parquet(
@@ -81,6 +144,7 @@ def save_sythetic_data(synthetic_data, table_name, database_name):
         path=synthetic_location,
         dataset=True,
         database=database_name,
+        catalog_id=catalog_id,
         table=synthetic_table_name,
         mode="overwrite",
         description=f"Sythetic data for {table_name} generated on {datetime.datetime.now()}"
@@ -99,31 +163,53 @@ def set_cw_logging(aws_region, log_group_name, log_stream_name):
 # main  running function of script that takes table name and database name as arguments  and generates sythetic data
 
 
-def main(aws_region, database_name, table_name, log_group_name, log_stream_name):
+def main(args):
+    aws_region = args.aws_region
+    database_name = args.database_name
+    # resource links make databases shared from other accounts visible to Athena under a local name
+    query_database = args.query_database or database_name
+    table_name = args.table_name
+    table_format = args.table_format
 
     boto3.setup_default_session(region_name=aws_region)
     # set up logging to cloudwatch
-    log_client = get_logwatch_client(aws_region)
     send_status = set_cw_logging(
-        aws_region, log_group_name, log_stream_name)
+        aws_region, args.log_group_name, args.log_stream_name)
 
-    send_status("Getting table data from Athena for table: " +
-                table_name + " in database: " + database_name + "...")
-    data = get_table(table_name, database_name)
+    if table_format == "Jdbc":
+        send_status("Getting table data through Glue connection " + args.connection + " for table: " +
+                    table_name + " in database: " + database_name + "...")
+        data = get_jdbc_table(
+            args.connection, args.table_location, args.sample_size, args.catalog_id)
+    else:
+        send_status("Getting " + table_format + " table data from Athena for table: " +
+                    table_name + " in database: " + database_name + "...")
+        data = get_table(table_name, query_database,
+                         table_format, args.sample_size)
     send_status("Generating sythetic data for table: " +
                 table_name + " in database: " + database_name + "...")
-    synthetic_data = generate_sythetic_data(data, send_status)
+    synthetic_data = generate_sythetic_data(data, args.model, send_status)
     send_status("Saving sythetic data to: " +
-                get_table_location(table_name, database_name) + "_sythetic in database: " + database_name + " with table name: " + table_name + "_sythetic")
-    save_sythetic_data(synthetic_data, table_name, database_name)
+                args.output_location + " in database: " + database_name + " with table name: " + table_name + "_sythetic")
+    save_sythetic_data(synthetic_data, table_name,
+                       database_name, args.output_location, args.catalog_id)
     send_status("done")
 
 
 if __name__ == "__main__":
-    if len(sys.argv) != 6:
-        sys.stderr.write("Error: Invalid number of arguments. \n")
-        sys.stderr.write(
-            "Usage: python3 single_table.py <aws_region> <database_name> <log_group_name> <log_stream_name> \n")
-        sys.exit(1)
-    else:
-        main(sys.argv[1], sys.argv[2], sys.argv[3], sys.argv[4], sys.argv[5])
+    parser = argparse.ArgumentParser(
+        description="Generates synthetic data for a single AWS Glue table")
+    parser.add_argument("aws_region")
+    parser.add_argument("database_name")
+    parser.add_argument("table_name")
+    parser.add_argument("log_group_name")
+    parser.add_argument("log_stream_name")
+    parser.add_argument("--table-format", default="Hive")
+    parser.add_argument("--output-location", required=True)
+    parser.add_argument("--connection", default="-")
+    parser.add_argument("--table-location", default="")
+    parser.add_argument("--sample-size", type=int, default=0)
+    parser.add_argument("--catalog-id", default=None)
+    parser.add_argument("--query-database", default=None)
+    parser.add_argument("--model", default="CTGAN", choices=MODELS.keys())
+    main(parser.parse_args())
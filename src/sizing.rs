@@ -0,0 +1,334 @@
+//! # sizing
+//! Instance types offered for the job and the recommendation of one of them.
+//! The recommendation sizes the memory of the instance from the number of values the model is
+//! trained on, taken from the statistics the Glue crawler records on the table, so that small
+//! tables do not run on a large instance and large tables do not run out of memory.
+//! The root volume is sized the same way, from the size of the table.
+use crate::cli::Architecture;
+use crate::error::{Error, Result};
+use crate::get_glue_data::GlueTable;
+use aws_sdk_ec2::model::{InstanceType, VolumeType};
+use std::ops::RangeInclusive;
+use strum_macros::{Display, EnumIter, EnumString};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+/// Memory of a value once loaded into a data frame, strings included
+const BYTES_PER_VALUE: u64 = 16;
+/// Growth of a columnar file once loaded, used when the crawler recorded no row count
+const IN_MEMORY_GROWTH: u64 = 5;
+/// Memory taken by the operating system and the Python runtime
+const BASE_MEMORY_GIB: u64 = 4;
+/// Disk taken by the operating system, Python and the SDV packages with their dependencies
+const BASE_VOLUME_GIB: u64 = 30;
+/// Disk used per byte of the table, for the downloaded data and the spill of the data frames
+const VOLUME_GROWTH: u64 = 3;
+/// Root volume of tables without statistics
+const DEFAULT_VOLUME_GIB: i32 = 200;
+/// Smallest root volume, the Amazon Linux 2023 snapshot takes 8 GiB
+const MIN_ROOT_VOLUME_GIB: i32 = 8;
+
+/// SDV model used to synthesize the table, displayed as the name of its Python class
+#[derive(Clone, Copy, Debug, Display, EnumIter, EnumString, PartialEq)]
+pub enum SynthesisModel {
+    #[strum(serialize = "CTGAN")]
+    Ctgan,
+    #[strum(serialize = "GaussianCopula")]
+    GaussianCopula,
+    #[strum(serialize = "CopulaGAN")]
+    CopulaGan,
+    #[strum(serialize = "TVAE")]
+    Tvae,
+}
+
+impl SynthesisModel {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SynthesisModel::Ctgan => "GAN, best fidelity on mixed data, slow to train",
+            SynthesisModel::GaussianCopula => "statistical, fast and light, fits simple tables",
+            SynthesisModel::CopulaGan => "GAN on top of Gaussian copulas, slow to train",
+            SynthesisModel::Tvae => "variational autoencoder, faster than the GANs",
+        }
+    }
+
+    /// Memory needed per value of the source, neural models one-hot encode categories and
+    /// keep the training batches next to the data
+    fn memory_factor(&self) -> u64 {
+        match self {
+            SynthesisModel::GaussianCopula => 3,
+            _ => 10,
+        }
+    }
+
+    /// Neural models train on all cores, fewer cores only make the job run longer
+    fn min_vcpus(&self) -> u32 {
+        match self {
+            SynthesisModel::GaussianCopula => 2,
+            _ => 8,
+        }
+    }
+}
+
+/// An instance type offered for the job
+pub struct InstanceOffer {
+    pub instance_type: InstanceType,
+    pub vcpus: u32,
+    pub memory_gib: u64,
+}
+
+/// Instance types offered for the job on the architecture, smallest first.
+/// Graviton offers have the same sizes as their Intel counterparts.
+pub fn instance_offers(architecture: Architecture) -> Vec<InstanceOffer> {
+    let offers = match architecture {
+        Architecture::X86_64 => [
+            (InstanceType::M6iLarge, 2, 8),
+            (InstanceType::M6iXlarge, 4, 16),
+            (InstanceType::M6i2xlarge, 8, 32),
+            (InstanceType::M6i4xlarge, 16, 64),
+            (InstanceType::M6i8xlarge, 32, 128),
+            (InstanceType::M6i16xlarge, 64, 256),
+            (InstanceType::R6i16xlarge, 64, 512),
+        ],
+        Architecture::Arm64 => [
+            (InstanceType::M6gLarge, 2, 8),
+            (InstanceType::M6gXlarge, 4, 16),
+            (InstanceType::M6g2xlarge, 8, 32),
+            (InstanceType::M6g4xlarge, 16, 64),
+            (InstanceType::M6g8xlarge, 32, 128),
+            (InstanceType::M6g16xlarge, 64, 256),
+            (InstanceType::R6g16xlarge, 64, 512),
+        ],
+    };
+    offers
+        .into_iter()
+        .map(|(instance_type, vcpus, memory_gib)| InstanceOffer {
+            instance_type,
+            vcpus,
+            memory_gib,
+        })
+        .collect()
+}
+
+/// Estimated memory in GiB the job needs, `None` when the table has no statistics
+pub fn required_memory_gib(table: &GlueTable, model: SynthesisModel) -> Option<u64> {
+    // a sample caps the rows read, whatever the size of the table
+    let rows = match (table.sample_size(), table.record_count()) {
+        (Some(sample_size), Some(record_count)) => Some(sample_size.min(record_count)),
+        (sample_size, record_count) => sample_size.or(record_count),
+    };
+    let in_memory_bytes = match (rows, table.column_count()) {
+        (Some(rows), columns) if columns > 0 => rows
+            .saturating_mul(columns as u64)
+            .saturating_mul(BYTES_PER_VALUE),
+        _ => table.size_bytes()?.saturating_mul(IN_MEMORY_GROWTH),
+    };
+    // catalog statistics are not validated, huge values saturate instead of overflowing
+    Some(
+        in_memory_bytes
+            .saturating_mul(model.memory_factor())
+            .div_ceil(GIB)
+            .saturating_add(BASE_MEMORY_GIB),
+    )
+}
+
+/// Returns the smallest offered instance type that fits the table and the model.
+/// Tables without statistics get 16 vCPUs, the size every job used to run on.
+pub fn recommend_instance_type(
+    table: &GlueTable,
+    model: SynthesisModel,
+    architecture: Architecture,
+) -> InstanceType {
+    let offers = instance_offers(architecture);
+    let offer = match required_memory_gib(table, model) {
+        Some(memory_gib) => offers
+            .iter()
+            .find(|offer| offer.memory_gib >= memory_gib && offer.vcpus >= model.min_vcpus())
+            .or_else(|| offers.last()),
+        None => offers.iter().find(|offer| offer.vcpus == 16),
+    };
+    offer
+        .map(|offer| offer.instance_type.clone())
+        .unwrap_or(InstanceType::M6i4xlarge)
+}
+
+/// Returns the sizes in GiB EBS accepts for a root volume of the type
+pub fn volume_size_range(volume_type: &VolumeType) -> RangeInclusive<i32> {
+    let (min, max) = match volume_type {
+        VolumeType::Io1 | VolumeType::Io2 => (4, 16384),
+        VolumeType::Standard => (1, 1024),
+        _ => (1, 16384),
+    };
+    min.max(MIN_ROOT_VOLUME_GIB)..=max
+}
+
+/// Checks the size given on the command line against the limits of the volume type
+pub fn check_volume_size(volume_type: &VolumeType, size_gib: i32) -> Result<()> {
+    let range = volume_size_range(volume_type);
+    if range.contains(&size_gib) {
+        return Ok(());
+    }
+    Err(Error::Config(format!(
+        "A {} root volume must be between {} and {} GiB, not {} GiB",
+        volume_type.as_str(),
+        range.start(),
+        range.end(),
+        size_gib
+    )))
+}
+
+/// Returns the size in GiB of the root volume for the table, from the size the crawler recorded,
+/// within the limits of the volume type
+pub fn recommend_volume_size(table: &GlueTable, volume_type: &VolumeType) -> i32 {
+    let size_gib = match table.size_bytes() {
+        Some(size_bytes) => {
+            let size_gib = size_bytes
+                .saturating_mul(VOLUME_GROWTH)
+                .div_ceil(GIB)
+                .saturating_add(BASE_VOLUME_GIB);
+            i32::try_from(size_gib).unwrap_or(i32::MAX)
+        }
+        None => DEFAULT_VOLUME_GIB,
+    };
+    let range = volume_size_range(volume_type);
+    size_gib.clamp(*range.start(), *range.end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_glue_data::GlueDatabase;
+    use aws_sdk_glue::model::{Column, StorageDescriptor, Table};
+
+    /// Table of `columns` columns with the statistics a crawler records, when given
+    fn glue_table(columns: usize, record_count: Option<u64>, size_bytes: Option<u64>) -> GlueTable {
+        let mut storage_descriptor = StorageDescriptor::builder().location("s3://bucket/orders/");
+        for column in 0..columns {
+            storage_descriptor =
+                storage_descriptor.columns(Column::builder().name(format!("c{}", column)).build());
+        }
+        let mut builder = Table::builder()
+            .name("orders")
+            .storage_descriptor(storage_descriptor.build());
+        if let Some(record_count) = record_count {
+            builder = builder.parameters("recordCount", record_count.to_string());
+        }
+        if let Some(size_bytes) = size_bytes {
+            builder = builder.parameters("sizeKey", size_bytes.to_string());
+        }
+        let database = GlueDatabase::new(
+            "eu-west-1".to_string(),
+            "123456789012".to_string(),
+            "123456789012".to_string(),
+            "sales".to_string(),
+            None,
+        );
+        GlueTable::new(database, &builder.build())
+    }
+
+    #[test]
+    fn required_memory_grows_with_the_values_and_the_model() {
+        let table = glue_table(10, Some(1_000_000), None);
+        assert_eq!(required_memory_gib(&table, SynthesisModel::Ctgan), Some(6));
+        assert_eq!(
+            required_memory_gib(&table, SynthesisModel::GaussianCopula),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn required_memory_is_capped_by_the_sample() {
+        let mut table = glue_table(10, Some(1_000_000_000), None);
+        table.set_sample_size(Some(100));
+        assert_eq!(required_memory_gib(&table, SynthesisModel::Ctgan), Some(5));
+    }
+
+    #[test]
+    fn required_memory_falls_back_to_the_size_of_the_files() {
+        let table = glue_table(10, None, Some(GIB));
+        assert_eq!(required_memory_gib(&table, SynthesisModel::Ctgan), Some(54));
+    }
+
+    #[test]
+    fn required_memory_saturates_on_huge_statistics() {
+        let table = glue_table(10, Some(u64::MAX), None);
+        assert_eq!(
+            required_memory_gib(&table, SynthesisModel::Ctgan),
+            Some(u64::MAX.div_ceil(GIB) + BASE_MEMORY_GIB)
+        );
+        let table = glue_table(0, None, Some(u64::MAX));
+        assert_eq!(
+            required_memory_gib(&table, SynthesisModel::GaussianCopula),
+            Some(u64::MAX.div_ceil(GIB) + BASE_MEMORY_GIB)
+        );
+        assert_eq!(
+            recommend_instance_type(&table, SynthesisModel::Ctgan, Architecture::X86_64),
+            InstanceType::R6i16xlarge
+        );
+    }
+
+    #[test]
+    fn required_memory_is_unknown_without_statistics() {
+        assert_eq!(
+            required_memory_gib(&glue_table(10, None, None), SynthesisModel::Ctgan),
+            None
+        );
+    }
+
+    #[test]
+    fn recommended_instance_fits_the_memory_and_the_cores_of_the_model() {
+        let table = glue_table(10, Some(1_000_000), None);
+        assert_eq!(
+            recommend_instance_type(&table, SynthesisModel::Ctgan, Architecture::X86_64),
+            InstanceType::M6i2xlarge
+        );
+        assert_eq!(
+            recommend_instance_type(&table, SynthesisModel::GaussianCopula, Architecture::Arm64),
+            InstanceType::M6gLarge
+        );
+        assert_eq!(
+            recommend_instance_type(
+                &glue_table(10, None, None),
+                SynthesisModel::Ctgan,
+                Architecture::X86_64
+            ),
+            InstanceType::M6i4xlarge
+        );
+    }
+
+    #[test]
+    fn recommended_volume_grows_with_the_table() {
+        assert_eq!(
+            recommend_volume_size(&glue_table(10, None, None), &VolumeType::Gp3),
+            DEFAULT_VOLUME_GIB
+        );
+        assert_eq!(
+            recommend_volume_size(&glue_table(10, None, Some(10 * GIB)), &VolumeType::Gp3),
+            60
+        );
+    }
+
+    #[test]
+    fn recommended_volume_stays_within_the_limits_of_the_type() {
+        let table = glue_table(10, None, Some(1024 * GIB));
+        assert_eq!(recommend_volume_size(&table, &VolumeType::Gp3), 3102);
+        assert_eq!(recommend_volume_size(&table, &VolumeType::Standard), 1024);
+        let huge = glue_table(10, None, Some(u64::MAX));
+        assert_eq!(recommend_volume_size(&huge, &VolumeType::Io2), 16384);
+    }
+
+    #[test]
+    fn volume_size_is_checked_against_the_type() {
+        assert_eq!(volume_size_range(&VolumeType::Gp2), 8..=16384);
+        assert_eq!(volume_size_range(&VolumeType::Io1), 8..=16384);
+        assert_eq!(volume_size_range(&VolumeType::Standard), 8..=1024);
+        assert!(check_volume_size(&VolumeType::Gp3, 8).is_ok());
+        assert!(check_volume_size(&VolumeType::Standard, 1024).is_ok());
+        assert!(matches!(
+            check_volume_size(&VolumeType::Standard, 1025),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            check_volume_size(&VolumeType::Gp3, 7),
+            Err(Error::Config(_))
+        ));
+    }
+}